@@ -42,14 +42,16 @@ fn main() {
         .whitelist_function("crypto_box_(keypair|easy|open_easy)")
         .whitelist_function("crypto_box_seal.*")
         .whitelist_function("crypto_secretbox_.+")
-        .whitelist_function("crypto_sign(_open|_keypair)?")
+        .whitelist_function("crypto_sign(_open|_keypair|_seed_keypair)?")
         .whitelist_function("crypto_sign_(verify_)?detached")
+        .whitelist_function("crypto_sign_ed25519_(pk|sk)_to_curve25519")
         .whitelist_function("crypto_generichash_(init|update|final|statebytes)")
         .whitelist_function("crypto_aead_xchacha20poly1305_ietf_.+")
         .whitelist_function("crypto_aead_aes256gcm_.+")
         .whitelist_function("crypto_kdf_(keygen|derive_from_key)")
-        .whitelist_function("sodium_bin2hex")
-        .whitelist_function("crypto_pwhash")
+        .whitelist_function("crypto_auth_hmacsha256(_init|_update|_final)?")
+        .whitelist_function("sodium_(bin2hex|hex2bin|memcmp)")
+        .whitelist_function("crypto_pwhash(_str(_verify)?)?")
         .whitelist_var("crypto_secretbox_.+")
         .whitelist_var("crypto_pwhash_.+")
         .whitelist_var("crypto_kdf_.+")
@@ -58,9 +60,10 @@ fn main() {
         .whitelist_var("crypto_secretstream_xchacha20poly1305_(A|HEADER|KEY)BYTES")
         .whitelist_var("crypto_secretstream_xchacha20poly1305_TAG_.*")
         .whitelist_var("crypto_box_(PUBLICKEY|SECRETKEY|MAC|NONCE)BYTES")
-        .whitelist_var("crypto_generichash_(STATE|KEY)?BYTES")
-        .whitelist_var("crypto_sign_(PUBLICKEY|SECRETKEY)?BYTES")
+        .whitelist_var("crypto_generichash_(STATE|KEY)?BYTES(_MAX)?")
+        .whitelist_var("crypto_sign_(PUBLICKEY|SECRETKEY|SEED)?BYTES")
         .whitelist_var("crypto_aead_aes256gcm_.+")
+        .whitelist_var("crypto_auth_hmacsha256_(KEY)?BYTES")
         .layout_tests(false)
         .generate()
         .unwrap()
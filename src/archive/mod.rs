@@ -1,30 +1,86 @@
 use std::cmp::min;
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::fs;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::io::SeekFrom;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
 
-use crate::archive::object::{ObjectEpilogue, ObjectInfo, ObjectType};
+use crate::archive::object::{hardlink_id, now, ObjectEpilogue, ObjectInfo, ObjectType};
 use crate::buffer::Buffer;
+use crate::encoding;
+use crate::errors;
+use crate::key::PublicKey;
 use crate::sodium;
+use crate::sodium::crypto_box;
+use crate::sodium::hashing;
 use crate::sodium::hashing::Hasher;
+use crate::sodium::kdf;
+use crate::sodium::kdf::KdfContext;
 use crate::sodium::pwhash;
-use crate::sodium::randombytes;
+use crate::sodium::pwhash::{PwhashAlgorithm, PwhashParams};
 use crate::sodium::secretstream;
 use crate::sodium::secretstream::SecretStream;
-use crate::zstd::{Compressor, Decompressor};
-use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
-use failure::{ensure, err_msg, format_err, Error, ResultExt};
+use crate::sodium::signing;
+use crate::sodium::signing::{StreamSigner, StreamVerifier};
+use crate::utils;
+use crate::utils::codecs;
+use crate::utils::CountingWriter;
+use crate::zstd::{compress_all, CompressionStats, Compressor, Decompressor};
+use anyhow::{ensure, Context, Error};
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
 use std::mem::size_of;
 
 pub mod object;
 
 const OPSLIMIT: u64 = 3;
+#[cfg(not(test))]
 const MEMLIMIT: usize = 1024 * 1024 * 1024;
+// Tests derive many archive keys, a gigabyte per derivation makes them slow and memory hungry.
+#[cfg(test)]
+const MEMLIMIT: usize = 8 * 1024 * 1024;
+const MAGIC: &[u8; 4] = b"SECR";
+const FORMAT_MAJOR_VERSION: u16 = 1;
+const FORMAT_MINOR_VERSION: u16 = 0;
+const MAC_CONTEXT: KdfContext = KdfContext(*b"arch_mac");
+const FILE_MAC_CONTEXT: KdfContext = KdfContext(*b"filemac ");
+const READ_BUFFER_SIZE: usize = 2 * 1024 * 1024;
+const CHUNK_BUFFER_SIZE: usize = 1024 * 1024;
+const FOOTER_MAGIC: &[u8; 4] = b"SEND";
+const FOOTER_BYTES: usize = 2 * size_of::<u64>() + 4;
+// Manifest hash and signature kept in plaintext before the footer, so that they can be checked without the password.
+const SIGNATURE_MAGIC: &[u8; 4] = b"SSIG";
+const SIGNATURE_TRAILER_BYTES: usize = hashing::HASH_BYTES + signing::SIG_BYTES + 4;
+const FRAME_MARGIN: usize = 128 * 1024;
+pub const SUPPORTED_MANIFEST_VERSION: u32 = 1;
+const ESTIMATE_SAMPLE_SIZE: usize = 1024 * 1024;
+const ESTIMATE_OBJECT_OVERHEAD: u64 = 1024;
+const READ_RETRIES: usize = 8;
+const WOULD_BLOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+// zstd accepts levels 1..=22, 0 meaning its default (3) and negative levels trading ratio for speed.
+pub const MAX_COMPRESSION_LEVEL: i32 = 22;
+pub const MIN_COMPRESSION_LEVEL: i32 = -100;
+// Objects are stored without compression. Kept outside the zstd range so that every zstd level stays usable.
+pub const COMPRESSION_NONE: i32 = i32::MIN;
+
+fn check_compression_level(level: i32) -> Result<(), Error> {
+    ensure!(
+        level == COMPRESSION_NONE
+            || (MIN_COMPRESSION_LEVEL..=MAX_COMPRESSION_LEVEL).contains(&level),
+        errors::Error::invalid_argument(format!(
+            "Compression level should be between {} and {}",
+            MIN_COMPRESSION_LEVEL, MAX_COMPRESSION_LEVEL
+        ))
+    );
+    Ok(())
+}
 
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 pub enum ChunkType {
@@ -33,6 +89,8 @@ pub enum ChunkType {
     Epilogue = 2,
     VolumeEnd = 3,
     End = 4,
+    Signature = 5,
+    ArchiveMetadata = 6,
 }
 
 impl TryFrom<u8> for ChunkType {
@@ -44,115 +102,778 @@ impl TryFrom<u8> for ChunkType {
             2 => Ok(ChunkType::Epilogue),
             3 => Ok(ChunkType::VolumeEnd),
             4 => Ok(ChunkType::End),
-            _ => Err(err_msg("Invalid chunk type")),
+            5 => Ok(ChunkType::Signature),
+            6 => Ok(ChunkType::ArchiveMetadata),
+            _ => Err(errors::Error::format("Invalid chunk type").into()),
         }
     }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Manifest {
+    #[serde(default = "default_manifest_version")]
+    pub version: u32,
     objects: Vec<ObjectInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_version: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub signed: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub mac: bool,
+}
+
+fn default_manifest_version() -> u32 {
+    1
+}
+
+impl Manifest {
+    pub fn from_slice(data: &[u8]) -> Result<Manifest, Error> {
+        let manifest: Manifest = serde_json::from_slice(data).context("Error parsing manifest")?;
+        ensure!(
+            manifest.version <= SUPPORTED_MANIFEST_VERSION,
+            errors::Error::format(format!(
+                "Manifest version {} is not supported (supported up to {}), please upgrade secrets",
+                manifest.version, SUPPORTED_MANIFEST_VERSION
+            ))
+        );
+        Ok(manifest)
+    }
+
+    pub fn find_by_path(&self, path: &[&str]) -> Option<&ObjectInfo> {
+        self.objects.iter().find(|object| object.path == path)
+    }
+
+    pub fn objects_sorted_by_size(&self) -> Vec<&ObjectInfo> {
+        let mut objects: Vec<&ObjectInfo> = self.objects.iter().collect();
+        objects.sort_by_key(|object| object.epilogue.as_ref().map_or(0, |epilogue| epilogue.size));
+        objects
+    }
+
+    pub fn find_all_by_type(&self, object_type: ObjectType) -> Vec<&ObjectInfo> {
+        self.objects
+            .iter()
+            .filter(|object| object.object_type == object_type)
+            .collect()
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.objects
+            .iter()
+            .filter_map(|object| object.epilogue.as_ref())
+            .map(|epilogue| epilogue.size)
+            .sum()
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.find_all_by_type(ObjectType::File).len()
+    }
+
+    pub fn directory_count(&self) -> usize {
+        self.find_all_by_type(ObjectType::Directory).len()
+    }
+
+    pub fn compressed_size_estimate(&self) -> Option<u64> {
+        self.objects
+            .iter()
+            .filter_map(|object| object.epilogue.as_ref())
+            .map(|epilogue| epilogue.compressed_size)
+            .sum()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Recipient {
+    id: String,
+    #[serde(
+        serialize_with = "codecs::to_base64",
+        deserialize_with = "codecs::from_base64"
+    )]
+    wrapped_key: Vec<u8>,
+}
+
+// The header is stored in plain text since it is needed to derive the key,
+// tampering with it changes the key and makes the stream fail to decrypt.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kdf", rename_all = "snake_case")]
+enum KeyDerivation {
+    Argon2id {
+        opslimit: u64,
+        memlimit: u64,
+        #[serde(
+            serialize_with = "codecs::to_base64",
+            deserialize_with = "codecs::from_base64"
+        )]
+        salt: Vec<u8>,
+    },
+    Argon2i {
+        opslimit: u64,
+        memlimit: u64,
+        #[serde(
+            serialize_with = "codecs::to_base64",
+            deserialize_with = "codecs::from_base64"
+        )]
+        salt: Vec<u8>,
+    },
+    SealedBox {
+        recipients: Vec<Recipient>,
+    },
+}
+
+impl KeyDerivation {
+    fn from_params(params: PwhashParams) -> Self {
+        let PwhashParams {
+            opslimit,
+            memlimit,
+            algorithm,
+            salt,
+        } = params;
+        let memlimit = memlimit as u64;
+        match algorithm {
+            PwhashAlgorithm::Argon2id => KeyDerivation::Argon2id {
+                opslimit,
+                memlimit,
+                salt,
+            },
+            PwhashAlgorithm::Argon2i => KeyDerivation::Argon2i {
+                opslimit,
+                memlimit,
+                salt,
+            },
+        }
+    }
+
+    fn into_params(self) -> Option<PwhashParams> {
+        let (opslimit, memlimit, algorithm, salt) = match self {
+            KeyDerivation::Argon2id {
+                opslimit,
+                memlimit,
+                salt,
+            } => (opslimit, memlimit, PwhashAlgorithm::Argon2id, salt),
+            KeyDerivation::Argon2i {
+                opslimit,
+                memlimit,
+                salt,
+            } => (opslimit, memlimit, PwhashAlgorithm::Argon2i, salt),
+            KeyDerivation::SealedBox { .. } => return None,
+        };
+        Some(PwhashParams {
+            opslimit,
+            memlimit: memlimit as usize,
+            algorithm,
+            salt,
+        })
+    }
+}
+
+fn write_header(header: &KeyDerivation) -> Result<Vec<u8>, Error> {
+    let header = serde_json::to_vec(header).context("Error serializing archive header")?;
+    let mut preamble = MAGIC.to_vec();
+    preamble.write_u16::<BigEndian>(FORMAT_MAJOR_VERSION)?;
+    preamble.write_u16::<BigEndian>(FORMAT_MINOR_VERSION)?;
+    preamble.write_u32::<BigEndian>(header.len() as u32)?;
+    preamble.extend_from_slice(&header);
+    Ok(preamble)
+}
+
+fn read_header<R: Read>(reader: &mut R) -> Result<(KeyDerivation, u64), Error> {
+    let mut magic = [0u8; 4];
+    reader
+        .read_exact(&mut magic)
+        .map_err(errors::Error::from)
+        .context("Error reading archive header")?;
+    ensure!(
+        &magic == MAGIC,
+        errors::Error::format(
+            "Not a secrets archive (missing magic), archives from older versions can be opened with new_legacy"
+        )
+    );
+    let major = reader
+        .read_u16::<BigEndian>()
+        .map_err(errors::Error::from)?;
+    let _minor = reader
+        .read_u16::<BigEndian>()
+        .map_err(errors::Error::from)?;
+    ensure!(
+        major == FORMAT_MAJOR_VERSION,
+        errors::Error::format(format!("Unsupported archive format version: {}", major))
+    );
+    let length = reader
+        .read_u32::<BigEndian>()
+        .map_err(errors::Error::from)?;
+    let mut header = vec![0u8; length as usize];
+    reader
+        .read_exact(&mut header)
+        .map_err(errors::Error::from)
+        .context("Error reading archive header")?;
+    let header = serde_json::from_slice(&header).context("Error parsing archive header")?;
+    Ok((
+        header,
+        (MAGIC.len() + 2 * size_of::<u16>() + size_of::<u32>()) as u64 + length as u64,
+    ))
+}
+
+fn password_preamble(
+    password: &str,
+    alg: PwhashAlgorithm,
+) -> Result<(Vec<u8>, Vec<u8>, PwhashParams), Error> {
+    password_preamble_with_params(password, PwhashParams::generate(alg, OPSLIMIT, MEMLIMIT))
+}
+
+fn password_preamble_with_params(
+    password: &str,
+    params: PwhashParams,
+) -> Result<(Vec<u8>, Vec<u8>, PwhashParams), Error> {
+    let key = params
+        .derive_key(password, secretstream::KEY_BYTES)
+        .context("Error deriving key from password")?;
+    let preamble = write_header(&KeyDerivation::from_params(params.clone()))?;
+    Ok((preamble, key, params))
+}
+
+fn object_mac_key(key: &[u8], object_index: u64) -> Result<Vec<u8>, Error> {
+    kdf::derive(key, hashing::HASH_BYTES, object_index, &FILE_MAC_CONTEXT)
+}
+
+fn recipient_id(pk: &[u8]) -> String {
+    let mut hasher = Hasher::new();
+    hasher.update(pk);
+    encoding::to_hex(&hasher.finalize())
+}
+
+fn recipients_preamble(recipients: &[&PublicKey]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    ensure!(
+        !recipients.is_empty(),
+        errors::Error::invalid_argument("No recipients specified")
+    );
+    let key = secretstream::generate_key();
+    let recipients = recipients
+        .iter()
+        .map(|recipient| -> Result<Recipient, Error> {
+            Ok(Recipient {
+                id: recipient_id(&recipient.box_pk),
+                wrapped_key: crypto_box::sealed_box_encrypt(&key, &recipient.box_pk)?,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let preamble = write_header(&KeyDerivation::SealedBox { recipients })?;
+    Ok((preamble, key))
 }
 
 fn append_volume_counter<P: AsRef<Path>>(path: P, volume_counter: u64) -> Result<PathBuf, Error> {
     let mut filename = path
         .as_ref()
         .file_name()
-        .ok_or_else(|| err_msg("Error getting filename component"))?
+        .ok_or_else(|| errors::Error::invalid_argument("Error getting filename component"))?
         .to_owned();
     filename.push(format!(".{:03}", volume_counter));
     Ok(path.as_ref().with_file_name(filename))
 }
 
-pub struct ArchiveWriter {
-    file: File,
+pub type VolumeProvider<T> = Box<dyn FnMut(u64) -> Result<T, Error>>;
+
+fn volume_path(
+    raw_path: &Path,
+    volume_size: Option<u64>,
+    volume_counter: u64,
+) -> Result<PathBuf, Error> {
+    match volume_size {
+        Some(_) => append_volume_counter(raw_path, volume_counter),
+        None => Ok(raw_path.to_path_buf()),
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum DuplicatePolicy {
+    Reject,
+    AllowLast,
+}
+
+pub struct ArchiveWriter<W: Write> {
+    file: W,
+    volume_provider: Option<VolumeProvider<W>>,
     pusher: SecretStream,
     objects: Vec<ObjectInfo>,
     compression_level: i32,
     volume_counter: u64,
     volume_size: Option<u64>,
     byte_count: u64,
-    raw_path: PathBuf,
+    total_bytes: u64,
+    raw_path: Option<PathBuf>,
+    temp_nonce: String,
+    sync_volume: Option<fn(&mut W) -> io::Result<()>>,
     ended: bool,
+    finished: bool,
+    mac_key: Vec<u8>,
+    mac: Option<Hasher>,
+    key: Vec<u8>,
+    keyed_hashes: bool,
+    header_count: u64,
+    pwhash_params: Option<PwhashParams>,
+    signer: Option<StreamSigner>,
+    read_buffer_size: usize,
+    read_retries: usize,
+    would_block_timeout: Duration,
+    incompressible_extensions: Vec<String>,
+    compression_stats: CompressionStats,
+    threads: usize,
+    hardlinks: Option<HashMap<(u64, u64), Vec<String>>>,
+    written_paths: HashMap<Vec<String>, String>,
+    duplicate_policy: DuplicatePolicy,
 }
 
-impl ArchiveWriter {
+impl ArchiveWriter<File> {
     pub fn new<P: AsRef<Path>>(
         path: P,
         password: &str,
         compression_level: Option<i32>,
         volume_size: Option<u64>,
     ) -> Result<Self, Error> {
-        let mut file = match volume_size {
-            Some(_) => File::create(append_volume_counter(path.as_ref(), 1)?)
-                .context("Error opening file")?,
-            None => File::create(path.as_ref()).context("Error opening file")?,
+        Self::new_with_alg(
+            path,
+            password,
+            PwhashAlgorithm::Argon2id,
+            compression_level,
+            volume_size,
+        )
+    }
+
+    pub fn new_with_alg<P: AsRef<Path>>(
+        path: P,
+        password: &str,
+        alg: PwhashAlgorithm,
+        compression_level: Option<i32>,
+        volume_size: Option<u64>,
+    ) -> Result<Self, Error> {
+        let (preamble, key, params) = password_preamble(password, alg)?;
+        Ok(Self::create(
+            path.as_ref(),
+            &preamble,
+            &key,
+            compression_level,
+            volume_size,
+        )?
+        .with_pwhash_params(params))
+    }
+
+    pub fn reencrypt<P: AsRef<Path>, Q: AsRef<Path>>(
+        input_path: P,
+        old_password: &str,
+        new_password: &str,
+        output_path: Q,
+        volume_size: Option<u64>,
+    ) -> Result<(), Error> {
+        let mut input = ArchiveReader::new(input_path, old_password)?;
+        let manifest = input.read_manifest_fast()?;
+        ensure!(
+            !manifest.signed,
+            errors::Error::invalid_argument("Signed archives cannot be re-encrypted")
+        );
+        let params = match &input.pwhash_params {
+            Some(params) => {
+                PwhashParams::generate(params.algorithm, params.opslimit, params.memlimit)
+            }
+            None => PwhashParams::generate(PwhashAlgorithm::Argon2id, OPSLIMIT, MEMLIMIT),
+        };
+        let (preamble, key, params) = password_preamble_with_params(new_password, params)?;
+        let mut output = Self::create(output_path.as_ref(), &preamble, &key, None, volume_size)?
+            .with_pwhash_params(params);
+        if manifest.mac {
+            output = output.with_hmac();
+        }
+        if manifest.objects.iter().any(|info| {
+            info.epilogue
+                .as_ref()
+                .map_or(false, |epilogue| epilogue.keyed_hash.is_some())
+        }) {
+            output = output.with_keyed_hashes();
+        }
+        if let Err(err) = output.copy_from(&mut input) {
+            return Err(output.abort_with(err));
+        }
+        output.end()
+    }
+
+    pub fn new_multi_recipient<P: AsRef<Path>>(
+        path: P,
+        recipients: &[&PublicKey],
+        compression_level: Option<i32>,
+        volume_size: Option<u64>,
+    ) -> Result<Self, Error> {
+        let (preamble, key) = recipients_preamble(recipients)?;
+        Self::create(
+            path.as_ref(),
+            &preamble,
+            &key,
+            compression_level,
+            volume_size,
+        )
+    }
+
+    pub fn estimate_output_size(input_paths: &[PathBuf]) -> Result<u64, Error> {
+        let mut total_size = 0u64;
+        let mut object_count = 0u64;
+        let mut sample = Vec::with_capacity(ESTIMATE_SAMPLE_SIZE);
+        for input_path in input_paths {
+            for path in utils::generate_tree_iter(input_path, true) {
+                let path = path?;
+                object_count += 1;
+                let metadata = fs::metadata(&path)?;
+                if !metadata.is_file() {
+                    continue;
+                }
+                total_size += metadata.len();
+                if sample.len() < ESTIMATE_SAMPLE_SIZE {
+                    File::open(&path)
+                        .map_err(errors::Error::from)?
+                        .take((ESTIMATE_SAMPLE_SIZE - sample.len()) as u64)
+                        .read_to_end(&mut sample)
+                        .map_err(errors::Error::from)?;
+                }
+            }
+        }
+        let ratio = if sample.is_empty() {
+            1.0
+        } else {
+            (compress_all(&sample, 3)?.len() as f64 / sample.len() as f64).min(1.0)
         };
-        let mut byte_count = 0u64;
-        let salt = randombytes(pwhash::SALT_BYTES);
-        file.write_all(&salt)?;
-        byte_count += salt.len() as u64;
-        let key = pwhash::pwhash(password, secretstream::KEY_BYTES, &salt, OPSLIMIT, MEMLIMIT)
-            .context("Error deriving key from password")?;
-        let mut params = vec![0u8; 2 * size_of::<u64>()];
-        BigEndian::write_u64_into(&[OPSLIMIT, MEMLIMIT as u64], &mut params);
-        file.write_all(&params)?;
-        byte_count += params.len() as u64;
-        let pusher = secretstream::SecretStream::new_push(&key).unwrap();
-        file.write_all(&pusher.get_header())?;
-        byte_count += pusher.get_header().len() as u64;
+        let chunk_overhead = (1 + size_of::<u32>() + 2 * secretstream::ADDITIONAL_BYTES) as u64;
+        let chunk_count = total_size / READ_BUFFER_SIZE as u64 + 3 * object_count;
+        Ok((total_size as f64 * ratio).ceil() as u64
+            + chunk_count * chunk_overhead
+            + object_count * ESTIMATE_OBJECT_OVERHEAD
+            + ESTIMATE_OBJECT_OVERHEAD)
+    }
+
+    fn create(
+        path: &Path,
+        preamble: &[u8],
+        key: &[u8],
+        compression_level: Option<i32>,
+        volume_size: Option<u64>,
+    ) -> Result<Self, Error> {
+        let raw_path = path.to_path_buf();
+        let temp_nonce = utils::temp_nonce();
+        let nonce = temp_nonce.clone();
+        let mut volume_provider: VolumeProvider<File> = Box::new(move |volume_counter| {
+            let path = volume_path(&raw_path, volume_size, volume_counter)?;
+            Ok(utils::create_new_file(utils::temp_path(&path, &nonce)?)
+                .map_err(errors::Error::from)
+                .context("Error creating volume")?)
+        });
+        let first_path = utils::temp_path(&volume_path(path, volume_size, 1)?, &temp_nonce)?;
+        let file = volume_provider(1)?;
+        let mut writer = Self::with_writer(
+            file,
+            Some(volume_provider),
+            preamble,
+            key,
+            compression_level,
+            volume_size,
+        )
+        .map_err(|err| {
+            let _ = fs::remove_file(&first_path);
+            err
+        })?;
+        writer.raw_path = Some(path.to_path_buf());
+        writer.temp_nonce = temp_nonce;
+        writer.sync_volume = Some(|file: &mut File| file.sync_all());
+        Ok(writer)
+    }
+}
+
+impl ArchiveWriter<CountingWriter> {
+    pub fn new_dry_run(password: &str, compression_level: Option<i32>) -> Result<Self, Error> {
+        Self::from_writer(CountingWriter::default(), password, compression_level)
+    }
+
+    pub fn estimated_size(&self) -> u64 {
+        self.file.bytes_written
+    }
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn from_writer(
+        file: W,
+        password: &str,
+        compression_level: Option<i32>,
+    ) -> Result<Self, Error> {
+        let (preamble, key, params) = password_preamble(password, PwhashAlgorithm::Argon2id)?;
+        Ok(
+            Self::with_writer(file, None, &preamble, &key, compression_level, None)?
+                .with_pwhash_params(params),
+        )
+    }
+
+    pub fn from_volume_provider(
+        mut volume_provider: VolumeProvider<W>,
+        password: &str,
+        compression_level: Option<i32>,
+        volume_size: u64,
+    ) -> Result<Self, Error> {
+        let (preamble, key, params) = password_preamble(password, PwhashAlgorithm::Argon2id)?;
+        let file = volume_provider(1)?;
+        Ok(Self::with_writer(
+            file,
+            Some(volume_provider),
+            &preamble,
+            &key,
+            compression_level,
+            Some(volume_size),
+        )?
+        .with_pwhash_params(params))
+    }
+
+    fn with_writer(
+        mut file: W,
+        volume_provider: Option<VolumeProvider<W>>,
+        preamble: &[u8],
+        key: &[u8],
+        compression_level: Option<i32>,
+        volume_size: Option<u64>,
+    ) -> Result<Self, Error> {
+        let compression_level = compression_level.unwrap_or(3);
+        check_compression_level(compression_level)?;
+        file.write_all(preamble).map_err(errors::Error::from)?;
+        let pusher = secretstream::SecretStream::new_push(key)?;
+        file.write_all(&pusher.get_header())
+            .map_err(errors::Error::from)?;
+        let byte_count = (preamble.len() + pusher.get_header().len()) as u64;
         Ok(Self {
             file,
+            volume_provider,
             pusher,
             objects: Vec::new(),
-            compression_level: compression_level.unwrap_or(3),
+            compression_level,
             volume_counter: 1,
             volume_size,
             byte_count,
-            raw_path: path.as_ref().to_path_buf(),
+            total_bytes: byte_count,
+            raw_path: None,
+            temp_nonce: String::new(),
+            sync_volume: None,
             ended: false,
+            finished: false,
+            mac_key: kdf::derive(key, hashing::HASH_BYTES, 1, &MAC_CONTEXT)?,
+            mac: None,
+            key: key.to_vec(),
+            keyed_hashes: false,
+            header_count: 0,
+            pwhash_params: None,
+            signer: None,
+            read_buffer_size: READ_BUFFER_SIZE,
+            read_retries: READ_RETRIES,
+            would_block_timeout: WOULD_BLOCK_TIMEOUT,
+            incompressible_extensions: Vec::new(),
+            compression_stats: CompressionStats::default(),
+            threads: 0,
+            hardlinks: None,
+            written_paths: HashMap::new(),
+            duplicate_policy: DuplicatePolicy::Reject,
         })
     }
 
+    pub fn with_read_buffer_size(mut self, bytes: usize) -> Self {
+        self.read_buffer_size = bytes;
+        self
+    }
+
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = policy;
+        self
+    }
+
+    fn check_duplicate(&mut self, object_path: &[String], source: &str) -> Result<(), Error> {
+        if let Some(previous) = self
+            .written_paths
+            .insert(object_path.to_vec(), source.to_owned())
+        {
+            ensure!(
+                self.duplicate_policy == DuplicatePolicy::AllowLast,
+                errors::Error::invalid_argument(format!(
+                    "Duplicate object path {}: {} conflicts with {}",
+                    object_path.join("/"),
+                    source,
+                    previous
+                ))
+            );
+        }
+        Ok(())
+    }
+
+    pub fn with_hardlinks(mut self) -> Self {
+        self.hardlinks = Some(HashMap::new());
+        self
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    pub fn with_read_retries(mut self, retries: usize) -> Self {
+        self.read_retries = retries;
+        self
+    }
+
+    pub fn with_would_block_timeout(mut self, timeout: Duration) -> Self {
+        self.would_block_timeout = timeout;
+        self
+    }
+
+    pub fn with_incompressible_extensions(mut self, exts: &[&str]) -> Self {
+        self.incompressible_extensions = exts
+            .iter()
+            .map(|ext| ext.trim_start_matches('.').to_lowercase())
+            .collect();
+        self
+    }
+
+    pub fn set_metadata(
+        &mut self,
+        metadata: HashMap<String, serde_json::Value>,
+    ) -> Result<(), Error> {
+        ensure!(
+            self.pusher.counter() == 0,
+            errors::Error::invalid_argument("Metadata must be written before any objects")
+        );
+        self.write_chunk(&serde_json::to_vec(&metadata)?, ChunkType::ArchiveMetadata)
+    }
+
+    pub fn with_hmac(mut self) -> Self {
+        self.mac = Some(Hasher::new_keyed(&self.mac_key));
+        self
+    }
+
+    fn with_pwhash_params(mut self, params: PwhashParams) -> Self {
+        self.pwhash_params = Some(params);
+        self
+    }
+
+    pub fn pwhash_params(&self) -> Option<&PwhashParams> {
+        self.pwhash_params.as_ref()
+    }
+
+    pub fn with_keyed_hashes(mut self) -> Self {
+        self.keyed_hashes = true;
+        self
+    }
+
+    pub fn with_signing_key(mut self, secret_key: &[u8]) -> Self {
+        self.signer = Some(StreamSigner::new(secret_key));
+        self
+    }
+
+    pub fn objects(&self) -> &[ObjectInfo] {
+        &self.objects
+    }
+
+    pub fn compression_stats(&self) -> CompressionStats {
+        self.compression_stats
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.total_bytes
+    }
+
+    fn volume_path(&self, volume_counter: u64) -> Result<PathBuf, Error> {
+        let raw_path = self
+            .raw_path
+            .as_ref()
+            .ok_or_else(|| errors::Error::invalid_argument("Archive has no output path"))?;
+        volume_path(raw_path, self.volume_size, volume_counter)
+    }
+
+    fn finish_volume(&mut self) -> Result<(), Error> {
+        self.file
+            .flush()
+            .map_err(errors::Error::from)
+            .context("Error flushing volume")?;
+        if let Some(sync_volume) = self.sync_volume {
+            sync_volume(&mut self.file)
+                .map_err(errors::Error::from)
+                .context("Error syncing volume")?;
+        }
+        if self.raw_path.is_some() {
+            let path = self.volume_path(self.volume_counter)?;
+            fs::rename(utils::temp_path(&path, &self.temp_nonce)?, &path)
+                .map_err(errors::Error::from)
+                .context("Error renaming volume")?;
+        }
+        Ok(())
+    }
+
     fn write_chunk_unchecked(&mut self, data: &[u8], part_type: ChunkType) -> Result<u64, Error> {
         let mut info = [0u8; size_of::<u32>() + 1];
         info[0] = part_type as u8;
         let clen = data.len() + secretstream::ADDITIONAL_BYTES;
         BigEndian::write_u32(&mut info[1..], clen as u32);
-        let encrypted_info = self.pusher.push(&info, None).unwrap();
-        let encrypted_data = self.pusher.push(data, None).unwrap();
-        assert_eq!(encrypted_data.len(), clen);
-        assert!(encrypted_data.len() as u64 <= std::u32::MAX as u64);
+        let encrypted_info = self.pusher.push(&info)?;
+        let encrypted_data = self.pusher.push(data)?;
+        if let Some(signer) = self.signer.as_mut() {
+            signer.update(&info);
+            signer.update(data);
+        }
+        ensure!(
+            encrypted_data.len() == clen && clen as u64 <= std::u32::MAX as u64,
+            errors::Error::crypto("Unexpected encrypted chunk length")
+        );
         self.file
             .write_all(&encrypted_info)
+            .map_err(errors::Error::from)
             .context("Error writing chunk info")?;
         self.file
             .write_all(&encrypted_data)
+            .map_err(errors::Error::from)
             .context("Error writing chunk data")?;
-        Ok((encrypted_info.len() + encrypted_data.len()) as u64)
+        if let Some(mac) = self.mac.as_mut() {
+            mac.update(&encrypted_info);
+            mac.update(&encrypted_data);
+        }
+        let size = (encrypted_info.len() + encrypted_data.len()) as u64;
+        self.total_bytes += size;
+        Ok(size)
     }
 
-    fn write_chunk(&mut self, data: &[u8], part_type: ChunkType) -> Result<(), Error> {
-        if let Some(volume_size) = self.volume_size {
-            let chunk_size = (4
-                + 1
-                + secretstream::ADDITIONAL_BYTES
-                + data.len()
-                + secretstream::ADDITIONAL_BYTES) as u64;
-            let extra_size =
-                (4 + 1 + secretstream::ADDITIONAL_BYTES + 8192 + secretstream::ADDITIONAL_BYTES)
-                    as u64;
-            if self.byte_count + chunk_size + extra_size + 4 * 1024 >= volume_size {
-                self.write_chunk_unchecked(&[], ChunkType::VolumeEnd)
-                    .context("Error writing VolumeEnd chunk")?;
-                self.file = File::create(append_volume_counter(
-                    &self.raw_path,
-                    self.volume_counter + 1,
-                )?)
-                .context("Error creating next volume")?;
-                self.volume_counter += 1;
-                self.byte_count = 0;
+    fn volume_full(&self, len: usize) -> bool {
+        match self.volume_size {
+            Some(volume_size) => {
+                let chunk_size =
+                    (4 + 1 + secretstream::ADDITIONAL_BYTES + len + secretstream::ADDITIONAL_BYTES)
+                        as u64;
+                let extra_size = (4
+                    + 1
+                    + secretstream::ADDITIONAL_BYTES
+                    + 8192
+                    + secretstream::ADDITIONAL_BYTES) as u64;
+                self.byte_count + chunk_size + extra_size + 4 * 1024 >= volume_size
             }
+            None => false,
+        }
+    }
+
+    fn next_volume(&mut self) -> Result<(), Error> {
+        self.write_chunk_unchecked(&[], ChunkType::VolumeEnd)
+            .context("Error writing VolumeEnd chunk")?;
+        self.finish_volume()?;
+        let volume_provider = self
+            .volume_provider
+            .as_mut()
+            .ok_or_else(|| errors::Error::invalid_argument("Archive has no volume provider"))?;
+        self.file =
+            volume_provider(self.volume_counter + 1).context("Error creating next volume")?;
+        self.volume_counter += 1;
+        self.byte_count = 0;
+        Ok(())
+    }
+
+    fn write_chunk(&mut self, data: &[u8], part_type: ChunkType) -> Result<(), Error> {
+        if part_type == ChunkType::Header {
+            self.header_count += 1;
+        }
+        if self.volume_full(data.len()) {
+            self.next_volume()?;
         }
         self.byte_count += self.write_chunk_unchecked(data, part_type)?;
         Ok(())
@@ -162,149 +883,861 @@ impl ArchiveWriter {
         &mut self,
         path: P,
         object_path: &[String],
-    ) -> Result<(), Error> {
+    ) -> Result<CompressionStats, Error> {
         let mut info = ObjectInfo::from_path(path.as_ref(), object_path)?;
-        self.write_chunk(&serde_json::to_vec(&info)?, ChunkType::Header)?;
         if info.object_type == ObjectType::Directory {
-            return Ok(());
+            self.write_chunk(&serde_json::to_vec(&info)?, ChunkType::Header)?;
+            self.objects.push(info);
+            return Ok(CompressionStats::default());
+        }
+        self.check_duplicate(object_path, &info.original_path)?;
+        if let Some(hardlinks) = self.hardlinks.as_mut() {
+            if let Some(id) = hardlink_id(path.as_ref())? {
+                if let Some(target) = hardlinks.get(&id) {
+                    info.object_type = ObjectType::Hardlink;
+                    info.link_target = Some(target.clone());
+                    self.write_chunk(&serde_json::to_vec(&info)?, ChunkType::Header)?;
+                    self.objects.push(info);
+                    return Ok(CompressionStats::default());
+                }
+                hardlinks.insert(id, object_path.to_vec());
+            }
+        }
+        if let Some(ext) = path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            let ext = ext.to_lowercase();
+            info.compressed = !self.incompressible_extensions.contains(&ext);
+        }
+        self.write_stream(info, File::open(&path).map_err(errors::Error::from)?)
+    }
+
+    pub fn write_bytes(
+        &mut self,
+        data: &[u8],
+        object_path: &[String],
+        name: &str,
+    ) -> Result<CompressionStats, Error> {
+        self.write_object_from_reader(&mut &data[..], name, object_path)
+    }
+
+    pub fn write_object_from_reader(
+        &mut self,
+        reader: &mut dyn Read,
+        name: &str,
+        object_path: &[String],
+    ) -> Result<CompressionStats, Error> {
+        self.check_duplicate(object_path, name)?;
+        let info = ObjectInfo {
+            object_type: ObjectType::File,
+            name: name.to_owned(),
+            original_path: "<stdin>".to_owned(),
+            path: object_path.to_vec(),
+            epilogue: None,
+            compressed: true,
+            uid: None,
+            gid: None,
+            link_target: None,
+            archived_at: now(),
+        };
+        self.write_stream(info, reader)
+    }
+
+    fn write_stream<R: Read>(
+        &mut self,
+        mut info: ObjectInfo,
+        mut reader: R,
+    ) -> Result<CompressionStats, Error> {
+        if self.compression_level == COMPRESSION_NONE {
+            info.compressed = false;
         }
-        let mut compressor = Compressor::new(self.compression_level);
-        let mut file = File::open(&path)?;
+        let mut compressor = Compressor::new_threaded(self.compression_level, self.threads)
+            .context("Error setting the number of compression threads")?;
+        self.write_chunk(&serde_json::to_vec(&info)?, ChunkType::Header)?;
         let mut hasher = Hasher::new();
-        let mut buf = vec![0u8; 2 * 1024 * 1024];
+        let mut keyed_hasher = if self.keyed_hashes {
+            Some(Hasher::new_keyed(&object_mac_key(
+                &self.key,
+                self.header_count - 1,
+            )?))
+        } else {
+            None
+        };
+        let mut buf = vec![0u8; self.read_buffer_size];
         let mut size = 0u64;
+        let mut frame_size = 0u64;
+        let mut retries = 0;
+        let mut blocked_since = None;
         loop {
-            let count = file.read(&mut buf)?;
+            let count = match reader.read(&mut buf) {
+                Ok(count) => count,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    let since = *blocked_since.get_or_insert_with(Instant::now);
+                    if since.elapsed() >= self.would_block_timeout {
+                        return Err(errors::Error::from(err))
+                            .context("Timed out waiting for input");
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+                Err(err)
+                    if err.kind() == io::ErrorKind::Interrupted && retries < self.read_retries =>
+                {
+                    retries += 1;
+                    continue;
+                }
+                Err(err) => return Err(errors::Error::from(err).into()),
+            };
+            retries = 0;
+            blocked_since = None;
             if count == 0 {
                 break;
             }
-            let compressed = compressor.compress(&buf[0..count]).unwrap();
-            if !compressed.is_empty() {
-                self.write_chunk(compressed, ChunkType::Data)?;
+            if info.compressed {
+                // End the zstd frame before switching volumes so that volumes mostly hold complete frames.
+                // A frame tail that does not fit is carried over to the next volume like any other chunk.
+                if frame_size > 0 && self.volume_full(count + FRAME_MARGIN) {
+                    let volume_counter = self.volume_counter;
+                    let rest = compressor.finish()?;
+                    if !rest.is_empty() {
+                        self.write_chunk(rest, ChunkType::Data)?;
+                    }
+                    if self.volume_counter == volume_counter {
+                        self.next_volume()?;
+                    }
+                    frame_size = 0;
+                }
+                frame_size += count as u64;
+                let compressed = compressor.compress(&buf[0..count])?;
+                if !compressed.is_empty() {
+                    self.write_chunk(compressed, ChunkType::Data)?;
+                }
+            } else {
+                self.write_chunk(&buf[0..count], ChunkType::Data)?;
             }
             hasher.update(&buf[0..count]);
+            if let Some(keyed_hasher) = keyed_hasher.as_mut() {
+                keyed_hasher.update(&buf[0..count]);
+            }
             size += count as u64;
         }
-        self.write_chunk(compressor.finish().unwrap(), ChunkType::Data)?;
+        let stats = if info.compressed {
+            self.write_chunk(compressor.finish()?, ChunkType::Data)?;
+            compressor.stats()
+        } else {
+            CompressionStats::new(size, size)
+        };
+        self.compression_stats = self.compression_stats.add(&stats);
         info.epilogue = Some(ObjectEpilogue {
-            hash: sodium::to_hex(&hasher.finalize()),
+            hash: encoding::to_hex(&hasher.finalize()),
             size,
+            compressed_size: Some(stats.output_bytes),
+            keyed_hash: keyed_hasher.map(|mut hasher| encoding::to_hex(&hasher.finalize())),
         });
         self.write_chunk(
             &serde_json::to_vec(info.epilogue.as_ref().unwrap())?,
             ChunkType::Epilogue,
         )?;
         self.objects.push(info);
-        Ok(())
+        Ok(stats)
     }
     pub fn end(&mut self) -> Result<(), Error> {
+        self.end_with(None)
+    }
+
+    pub fn end_signed(&mut self, secret_key: &[u8]) -> Result<(), Error> {
+        ensure!(
+            !self.ended,
+            errors::Error::invalid_argument("Archive already ended")
+        );
+        self.end_with(Some(secret_key))
+    }
+
+    fn end_with(&mut self, manifest_key: Option<&[u8]>) -> Result<(), Error> {
         if !self.ended {
             self.ended = true;
-            self.write_chunk(
-                &serde_json::to_vec(&Manifest {
-                    objects: self.objects.clone(),
-                })?,
-                ChunkType::End,
-            )?;
+            if let Some(signer) = self.signer.take() {
+                let signature = signer.finalize()?;
+                self.write_chunk(&signature, ChunkType::Signature)?;
+            }
+            let manifest = serde_json::to_vec(&Manifest {
+                version: SUPPORTED_MANIFEST_VERSION,
+                objects: self.objects.clone(),
+                created_at: now(),
+                tool_version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+                signed: manifest_key.is_some(),
+                mac: self.mac.is_some(),
+            })?;
+            self.write_chunk(&manifest, ChunkType::End)?;
+            let end_size =
+                (1 + size_of::<u32>() + 2 * secretstream::ADDITIONAL_BYTES + manifest.len()) as u64;
+            let mut footer = Vec::with_capacity(FOOTER_BYTES);
+            footer.write_u64::<BigEndian>(self.byte_count - end_size)?;
+            footer.write_u64::<BigEndian>(self.pusher.counter() - 2)?;
+            footer.extend_from_slice(FOOTER_MAGIC);
+            if let Some(mac) = self.mac.as_mut() {
+                let tag = mac.finalize();
+                self.file
+                    .write_all(&tag)
+                    .map_err(errors::Error::from)
+                    .context("Error writing MAC")?;
+                self.total_bytes += tag.len() as u64;
+            }
+            if let Some(secret_key) = manifest_key {
+                let mut hasher = Hasher::new();
+                hasher.update(&manifest);
+                let manifest_hash = hasher.finalize();
+                let mut trailer = Vec::with_capacity(SIGNATURE_TRAILER_BYTES);
+                trailer.extend_from_slice(&manifest_hash);
+                trailer.extend_from_slice(&signing::sign_detached(&manifest_hash, secret_key)?);
+                trailer.extend_from_slice(SIGNATURE_MAGIC);
+                self.file
+                    .write_all(&trailer)
+                    .map_err(errors::Error::from)
+                    .context("Error writing signature trailer")?;
+                self.total_bytes += trailer.len() as u64;
+            }
+            self.file
+                .write_all(&footer)
+                .map_err(errors::Error::from)
+                .context("Error writing footer")?;
+            self.total_bytes += footer.len() as u64;
+            self.finish_volume()?;
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    fn copy_from<R: Read>(&mut self, input: &mut ArchiveReader<R>) -> Result<(), Error> {
+        let mut first = true;
+        while let Some(mut object) = input.read_object()? {
+            if first {
+                first = false;
+                if let Some(metadata) = object.archive.metadata.clone() {
+                    self.set_metadata(metadata)?;
+                }
+            }
+            let mut info = object.object_info.clone();
+            if info.object_type != ObjectType::File {
+                self.write_chunk(&serde_json::to_vec(&info)?, ChunkType::Header)?;
+                self.objects.push(info);
+                continue;
+            }
+            info.epilogue = None;
+            self.write_stream(info, &mut object)?;
+            let expected = object
+                .object_epilogue
+                .as_ref()
+                .ok_or_else(|| errors::Error::format("Object has no epilogue"))?;
+            let actual = self.objects.last().unwrap().epilogue.as_ref().unwrap();
+            ensure!(
+                expected.hash == actual.hash,
+                errors::Error::crypto(format!(
+                    "Hash mismatch for {}",
+                    object.object_info.path.join("/")
+                ))
+            );
+        }
+        if first {
+            if let Some(metadata) = input.metadata.clone() {
+                self.set_metadata(metadata)?;
+            }
         }
+        ensure!(
+            input.signature.is_none(),
+            errors::Error::invalid_argument("Signed archives cannot be re-encrypted")
+        );
         Ok(())
     }
+
+    pub fn abort(mut self) -> Result<(), Error> {
+        self.discard()
+    }
+
+    /// Discards the archive after `err`, keeping `err` as the error and attaching any cleanup failure to it
+    pub fn abort_with(self, err: Error) -> Error {
+        match self.abort() {
+            Ok(()) => err,
+            Err(abort_err) => err.context(format!(
+                "Error discarding incomplete archive: {:#}",
+                abort_err
+            )),
+        }
+    }
+
+    fn discard(&mut self) -> Result<(), Error> {
+        self.ended = true;
+        self.finished = true;
+        if self.raw_path.is_none() {
+            return Ok(());
+        }
+        let mut paths = Vec::new();
+        for counter in 1..self.volume_counter {
+            paths.push(self.volume_path(counter)?);
+        }
+        paths.push(utils::temp_path(
+            self.volume_path(self.volume_counter)?,
+            &self.temp_nonce,
+        )?);
+        let mut result: Result<(), Error> = Ok(());
+        for path in paths {
+            if let Err(err) = fs::remove_file(&path) {
+                if result.is_ok() {
+                    result = Err(errors::Error::from(err))
+                        .with_context(|| format!("Error removing volume {}", path.display()));
+                }
+            }
+        }
+        result
+    }
 }
 
-impl Drop for ArchiveWriter {
+impl<W: Write> Drop for ArchiveWriter<W> {
+    // An archive that was not ended successfully is incomplete, so it is removed instead of
+    // being finalized into a truncated but valid-looking archive.
     fn drop(&mut self) {
-        self.end().unwrap();
+        if !self.finished {
+            let _ = self.discard();
+        }
     }
 }
 
-pub struct ArchiveReader {
-    file: File,
+fn read_encrypted_chunk<R: Read>(
+    file: &mut R,
+    puller: &mut SecretStream,
+) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let mut encrypted_info = [0u8; 1 + size_of::<u32>() + secretstream::ADDITIONAL_BYTES];
+    file.read_exact(&mut encrypted_info)
+        .map_err(errors::Error::from)?;
+    let info = puller
+        .pull(&encrypted_info)
+        .context("Error decrypting chunk info")?;
+    let clen = BigEndian::read_u32(&info[1..]);
+    let mut ciphertext = vec![0u8; clen as usize];
+    file.read_exact(&mut ciphertext)
+        .map_err(errors::Error::from)?;
+    let chunk = puller
+        .pull(&ciphertext)
+        .context("Error decrypting chunk data")?;
+    Ok((info, chunk))
+}
+
+pub struct ArchiveReader<R: Read> {
+    file: R,
+    volume_provider: Option<VolumeProvider<R>>,
     puller: SecretStream,
     pub manifest: Option<Manifest>,
-    raw_path: PathBuf,
-    volume_counter: Option<u64>,
+    pub metadata: Option<HashMap<String, serde_json::Value>>,
+    raw_path: Option<PathBuf>,
+    volume_counter: u64,
+    data_offset: u64,
+    mac_key: Vec<u8>,
+    key: Vec<u8>,
+    header_count: u64,
+    pwhash_params: Option<PwhashParams>,
+    verifier: Option<StreamVerifier>,
+    verify_key: Option<Vec<u8>>,
+    signature: Option<Vec<u8>>,
+    is_multi_volume: bool,
+    chunk_buffer_size: usize,
+    decompressor: Decompressor,
 }
 
-impl ArchiveReader {
+fn volume_paths(raw_path: &Path, is_multi_volume: bool) -> Result<Vec<PathBuf>, Error> {
+    let filename = raw_path
+        .file_name()
+        .ok_or_else(|| errors::Error::invalid_argument("Error getting filename component"))?
+        .to_str()
+        .ok_or_else(|| errors::Error::invalid_argument("Error decoding filename"))?;
+    let mut paths = vec![raw_path.to_path_buf()];
+    if !is_multi_volume {
+        return Ok(paths);
+    }
+    let prefix = &filename[..filename.len() - 4];
+    for counter in 2.. {
+        let path = raw_path.with_file_name(format!("{}.{:03}", prefix, counter));
+        if !path.exists() {
+            break;
+        }
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+fn read_signature_trailer(path: &Path) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let mut file = File::open(path)
+        .map_err(errors::Error::from)
+        .context("Error opening last volume")?;
+    ensure!(
+        file.metadata().map_err(errors::Error::from)?.len()
+            >= (SIGNATURE_TRAILER_BYTES + FOOTER_BYTES) as u64,
+        errors::Error::not_found("Archive is not signed")
+    );
+    file.seek(SeekFrom::End(
+        -((SIGNATURE_TRAILER_BYTES + FOOTER_BYTES) as i64),
+    ))
+    .map_err(errors::Error::from)
+    .context("Error seeking to signature trailer")?;
+    let mut trailer = vec![0u8; SIGNATURE_TRAILER_BYTES];
+    file.read_exact(&mut trailer).map_err(errors::Error::from)?;
+    ensure!(
+        &trailer[SIGNATURE_TRAILER_BYTES - SIGNATURE_MAGIC.len()..] == SIGNATURE_MAGIC,
+        errors::Error::not_found("Archive is not signed")
+    );
+    let signature = trailer[hashing::HASH_BYTES..hashing::HASH_BYTES + signing::SIG_BYTES].to_vec();
+    trailer.truncate(hashing::HASH_BYTES);
+    Ok((trailer, signature))
+}
+
+fn detect_multi_volume(path: &Path) -> Result<bool, Error> {
+    let filename = path
+        .file_name()
+        .ok_or_else(|| errors::Error::invalid_argument("Error getting filename component"))?
+        .to_str()
+        .ok_or_else(|| errors::Error::invalid_argument("Error decoding filename"))?;
+    if filename.ends_with(".001") {
+        return Ok(true);
+    }
+    let is_later_volume = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => extension.len() == 3 && extension.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    };
+    ensure!(
+        !is_later_volume,
+        errors::Error::invalid_argument(format!(
+            "{} is not the first volume, please open the .001 volume instead",
+            path.display()
+        ))
+    );
+    Ok(false)
+}
+
+fn check_volume_sequence(first_volume: &Path) -> Result<(), Error> {
+    let filename = first_volume
+        .file_name()
+        .ok_or_else(|| errors::Error::invalid_argument("Error getting filename component"))?
+        .to_str()
+        .ok_or_else(|| errors::Error::invalid_argument("Error decoding filename"))?;
+    let prefix = &filename[..filename.len() - 3];
+    let directory = match first_volume.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut counters = Vec::new();
+    for entry in fs::read_dir(directory).context("Error listing volumes")? {
+        let name = entry?.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+        if name.len() == prefix.len() + 3 && name.starts_with(prefix) {
+            if let Ok(counter) = name[prefix.len()..].parse::<u64>() {
+                counters.push(counter);
+            }
+        }
+    }
+    let last = counters.iter().cloned().max().unwrap_or(1);
+    let missing: Vec<String> = (1..=last)
+        .filter(|counter| !counters.contains(counter))
+        .map(|counter| format!("{:03}", counter))
+        .collect();
+    ensure!(
+        missing.is_empty(),
+        errors::Error::not_found(format!("Missing volumes: {}", missing.join(", ")))
+    );
+    Ok(())
+}
+
+impl ArchiveReader<File> {
     pub fn new<P: AsRef<Path>>(path: P, password: &str) -> Result<Self, Error> {
-        let mut file = File::open(path.as_ref()).context("Error opening archive for read")?;
+        let is_multi_volume = detect_multi_volume(path.as_ref())?;
+        if is_multi_volume {
+            check_volume_sequence(path.as_ref())?;
+        }
+        let file = File::open(path.as_ref())
+            .map_err(errors::Error::from)
+            .context("Error opening archive for read")?;
+        Self::from_reader(file, password)?.with_path(path.as_ref(), is_multi_volume)
+    }
+
+    pub fn check_password<P: AsRef<Path>>(path: P, password: &str) -> Result<bool, Error> {
+        let file = File::open(path.as_ref())
+            .map_err(errors::Error::from)
+            .context("Error opening archive for read")?;
+        let mut reader = Self::from_reader(file, password)?;
+        let mut encrypted_info = [0u8; 1 + size_of::<u32>() + secretstream::ADDITIONAL_BYTES];
+        reader
+            .file
+            .read_exact(&mut encrypted_info)
+            .map_err(errors::Error::from)
+            .context("Error reading first chunk")?;
+        Ok(reader.puller.pull(&encrypted_info).is_ok())
+    }
+
+    pub fn new_legacy<P: AsRef<Path>>(path: P, password: &str) -> Result<Self, Error> {
+        let is_multi_volume = detect_multi_volume(path.as_ref())?;
+        if is_multi_volume {
+            check_volume_sequence(path.as_ref())?;
+        }
+        let file = File::open(path.as_ref())
+            .map_err(errors::Error::from)
+            .context("Error opening archive for read")?;
+        Self::from_reader_legacy(file, password)?.with_path(path.as_ref(), is_multi_volume)
+    }
+
+    pub fn new_with_key_ring<P: AsRef<Path>>(path: P, pk: &[u8], sk: &[u8]) -> Result<Self, Error> {
+        let is_multi_volume = detect_multi_volume(path.as_ref())?;
+        if is_multi_volume {
+            check_volume_sequence(path.as_ref())?;
+        }
+        let file = File::open(path.as_ref())
+            .map_err(errors::Error::from)
+            .context("Error opening archive for read")?;
+        Self::from_reader_with_key_ring(file, pk, sk)?.with_path(path.as_ref(), is_multi_volume)
+    }
+
+    fn with_path(mut self, path: &Path, is_multi_volume: bool) -> Result<Self, Error> {
+        let filename = path
+            .file_name()
+            .ok_or_else(|| errors::Error::invalid_argument("Error getting filename component"))?
+            .to_str()
+            .ok_or_else(|| errors::Error::invalid_argument("Error decoding filename"))?;
+        if is_multi_volume {
+            let prefix = filename[..filename.len() - 4].to_owned();
+            let raw_path = path.to_path_buf();
+            self.volume_provider = Some(Box::new(move |volume_counter| {
+                let path = raw_path.with_file_name(format!("{}.{:03}", prefix, volume_counter));
+                Ok(File::open(path)
+                    .map_err(errors::Error::from)
+                    .context("Error opening next volume")?)
+            }));
+        }
+        self.is_multi_volume = is_multi_volume;
+        self.raw_path = Some(path.to_path_buf());
+        Ok(self)
+    }
+
+    fn volume_paths(&self) -> Result<Vec<PathBuf>, Error> {
+        let raw_path = self
+            .raw_path
+            .as_ref()
+            .ok_or_else(|| errors::Error::invalid_argument("Archive has no input path"))?;
+        volume_paths(raw_path, self.is_multi_volume)
+    }
+
+    pub fn verify_signature_at<P: AsRef<Path>>(path: P, public_key: &[u8]) -> Result<bool, Error> {
+        let path = path.as_ref();
+        let last_volume = volume_paths(path, detect_multi_volume(path)?)?
+            .pop()
+            .unwrap();
+        let (manifest_hash, signature) = read_signature_trailer(&last_volume)?;
+        signing::verify_detached(&manifest_hash, &signature, public_key)
+    }
+
+    pub fn verify_signature(&self, public_key: &[u8]) -> Result<bool, Error> {
+        let manifest = self.read_manifest_chunk()?;
+        ensure!(
+            Manifest::from_slice(&manifest)?.signed,
+            errors::Error::not_found("Archive manifest is not signed")
+        );
+        let (manifest_hash, signature) =
+            read_signature_trailer(&self.volume_paths()?.pop().unwrap())?;
+        let mut hasher = Hasher::new();
+        hasher.update(&manifest);
+        ensure!(
+            sodium::memcmp(&hasher.finalize(), &manifest_hash),
+            errors::Error::crypto("Signature trailer does not match the manifest")
+        );
+        signing::verify_detached(&manifest_hash, &signature, public_key)
+    }
+
+    pub fn verify_hmac(&self) -> Result<bool, Error> {
+        let manifest = self.read_manifest_fast()?;
+        ensure!(manifest.mac, errors::Error::not_found("Archive has no MAC"));
+        let tail_bytes = hashing::HASH_BYTES
+            + FOOTER_BYTES
+            + if manifest.signed {
+                SIGNATURE_TRAILER_BYTES
+            } else {
+                0
+            };
+        let mut mac = Hasher::new_keyed(&self.mac_key);
+        let mut tail = Vec::new();
+        let mut buf = vec![0u8; 1024 * 1024];
+        for (index, path) in self.volume_paths()?.iter().enumerate() {
+            let mut file = File::open(path)
+                .map_err(errors::Error::from)
+                .context("Error opening volume")?;
+            if index == 0 {
+                file.seek(SeekFrom::Start(self.data_offset))
+                    .map_err(errors::Error::from)?;
+            }
+            loop {
+                let count = file.read(&mut buf).map_err(errors::Error::from)?;
+                if count == 0 {
+                    break;
+                }
+                tail.extend_from_slice(&buf[..count]);
+                if tail.len() > tail_bytes {
+                    let len = tail.len() - tail_bytes;
+                    mac.update(&tail[..len]);
+                    tail.drain(..len);
+                }
+            }
+        }
+        ensure!(
+            tail.len() == tail_bytes,
+            errors::Error::format("Archive is too short")
+        );
+        Ok(sodium::memcmp(
+            &mac.finalize(),
+            &tail[..hashing::HASH_BYTES],
+        ))
+    }
+
+    pub fn read_manifest_fast(&self) -> Result<Manifest, Error> {
+        Manifest::from_slice(&self.read_manifest_chunk()?)
+    }
+
+    fn read_manifest_chunk(&self) -> Result<Vec<u8>, Error> {
+        let path = self.volume_paths()?.pop().unwrap();
+        let mut file = File::open(&path)
+            .map_err(errors::Error::from)
+            .context("Error opening last volume")?;
+        file.seek(SeekFrom::End(-(FOOTER_BYTES as i64)))
+            .map_err(errors::Error::from)
+            .context("Error seeking to footer")?;
+        let offset = file.read_u64::<BigEndian>().map_err(errors::Error::from)?;
+        let counter = file.read_u64::<BigEndian>().map_err(errors::Error::from)?;
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic).map_err(errors::Error::from)?;
+        ensure!(
+            &magic == FOOTER_MAGIC,
+            errors::Error::format("Archive has no footer")
+        );
+        file.seek(SeekFrom::Start(offset))
+            .map_err(errors::Error::from)?;
+        let mut puller = self.puller.fork_pull(counter)?;
+        let (info, chunk) = read_encrypted_chunk(&mut file, &mut puller)?;
+        ensure!(
+            ChunkType::try_from(info[0])? == ChunkType::End,
+            errors::Error::format("Footer does not point to the end chunk")
+        );
+        Ok(chunk)
+    }
+}
+
+impl<R: Read> ArchiveReader<R> {
+    pub fn from_reader(mut file: R, password: &str) -> Result<Self, Error> {
+        let (header, offset) = read_header(&mut file)?;
+        let params = header
+            .into_params()
+            .ok_or_else(|| errors::Error::invalid_argument("Archive is not password protected"))?;
+        let key = params
+            .derive_key(password, secretstream::KEY_BYTES)
+            .context("Error deriving archive key")?;
+        Ok(Self::with_key(file, &key, offset)?.with_pwhash_params(params))
+    }
+
+    pub fn from_reader_legacy(mut file: R, password: &str) -> Result<Self, Error> {
         let mut salt = vec![0u8; pwhash::SALT_BYTES];
         file.read_exact(&mut salt)
+            .map_err(errors::Error::from)
             .context("Error reading password hashing salt")?;
-        let opslimit = file.read_u64::<BigEndian>()?;
-        let memlimit = file.read_u64::<BigEndian>()?;
-        let key = pwhash::pwhash(
-            password,
-            secretstream::KEY_BYTES,
-            &salt,
+        let opslimit = file.read_u64::<BigEndian>().map_err(errors::Error::from)?;
+        let memlimit = file.read_u64::<BigEndian>().map_err(errors::Error::from)?;
+        let params = PwhashParams {
             opslimit,
-            memlimit as usize,
-        )
-        .context("Error deriving archive key")?;
+            memlimit: memlimit as usize,
+            algorithm: PwhashAlgorithm::Argon2id,
+            salt,
+        };
+        let key = params
+            .derive_key(password, secretstream::KEY_BYTES)
+            .context("Error deriving archive key")?;
+        let offset = (pwhash::SALT_BYTES + 2 * size_of::<u64>()) as u64;
+        Ok(Self::with_key(file, &key, offset)?.with_pwhash_params(params))
+    }
+
+    pub fn from_reader_with_key_ring(mut file: R, pk: &[u8], sk: &[u8]) -> Result<Self, Error> {
+        let (recipients, offset) = match read_header(&mut file)? {
+            (KeyDerivation::SealedBox { recipients }, offset) => (recipients, offset),
+            _ => {
+                return Err(errors::Error::invalid_argument(
+                    "Archive is not encrypted to recipients",
+                )
+                .into())
+            }
+        };
+        let id = recipient_id(pk);
+        let key = recipients
+            .iter()
+            .filter(|recipient| recipient.id == id)
+            .find_map(|recipient| {
+                crypto_box::sealed_box_decrypt(&recipient.wrapped_key, pk, sk).ok()
+            })
+            .ok_or_else(|| errors::Error::not_found("No matching recipient found"))?;
+        Self::with_key(file, &key, offset)
+    }
+
+    fn with_pwhash_params(mut self, params: PwhashParams) -> Self {
+        self.pwhash_params = Some(params);
+        self
+    }
+
+    pub fn pwhash_params(&self) -> Option<&PwhashParams> {
+        self.pwhash_params.as_ref()
+    }
+
+    pub fn with_volume_provider(mut self, volume_provider: VolumeProvider<R>) -> Self {
+        self.volume_provider = Some(volume_provider);
+        self.is_multi_volume = true;
+        self
+    }
+
+    fn with_key(mut file: R, key: &[u8], offset: u64) -> Result<Self, Error> {
         let mut header = vec![0u8; secretstream::HEADER_BYTES];
-        file.read_exact(&mut header)?;
-        let puller = secretstream::SecretStream::new_pull(&header, &key)
+        file.read_exact(&mut header).map_err(errors::Error::from)?;
+        let puller = secretstream::SecretStream::new_pull(&header, key)
             .context("Error opening secretstream for read")?;
         Ok(Self {
             file,
+            volume_provider: None,
             puller,
             manifest: None,
-            raw_path: path.as_ref().to_path_buf(),
-            volume_counter: None,
+            metadata: None,
+            raw_path: None,
+            volume_counter: 1,
+            data_offset: offset + header.len() as u64,
+            mac_key: kdf::derive(key, hashing::HASH_BYTES, 1, &MAC_CONTEXT)?,
+            key: key.to_vec(),
+            header_count: 0,
+            pwhash_params: None,
+            verifier: Some(StreamVerifier::new()),
+            verify_key: None,
+            signature: None,
+            is_multi_volume: false,
+            chunk_buffer_size: CHUNK_BUFFER_SIZE,
+            decompressor: Decompressor::new(),
         })
     }
 
-    pub fn read_object(&mut self) -> Result<Option<ObjectReader>, Error> {
+    pub fn with_chunk_buffer_size(mut self, bytes: usize) -> Self {
+        self.chunk_buffer_size = bytes;
+        self
+    }
+
+    pub fn with_verify_key(mut self, public_key: &[u8]) -> Self {
+        self.verify_key = Some(public_key.to_vec());
+        self
+    }
+
+    fn verify_stream_signature(&mut self) -> Result<(), Error> {
+        let public_key = match &self.verify_key {
+            Some(public_key) => public_key,
+            None => return Ok(()),
+        };
+        let signature = self
+            .signature
+            .as_ref()
+            .ok_or_else(|| errors::Error::not_found("Archive is not signed"))?;
+        let verifier = self
+            .verifier
+            .take()
+            .ok_or_else(|| errors::Error::invalid_argument("Archive signature already verified"))?;
+        ensure!(
+            verifier.verify(signature, public_key)?,
+            errors::Error::crypto("Archive signature verification failed")
+        );
+        Ok(())
+    }
+
+    pub fn read_object(&mut self) -> Result<Option<ObjectReader<R>>, Error> {
         let (part_type, part) = self.read_chunk()?;
         if part_type == ChunkType::End {
-            self.manifest = Some(serde_json::from_slice(&part)?);
+            self.manifest = Some(Manifest::from_slice(&part)?);
             return Ok(None);
         }
-        let info: ObjectInfo = serde_json::from_slice(part.deref()).unwrap();
+        ensure!(
+            part_type == ChunkType::Header,
+            errors::Error::format("Expected an object header")
+        );
+        let info: ObjectInfo =
+            serde_json::from_slice(part.deref()).context("Error parsing object header")?;
+        let chunk_buffer_size = self.chunk_buffer_size;
+        let object_index = self
+            .header_count
+            .checked_sub(1)
+            .ok_or_else(|| errors::Error::format("Object header was not counted"))?;
+        let keyed_hasher = Hasher::new_keyed(&object_mac_key(&self.key, object_index)?);
+        self.decompressor.reset()?;
         Ok(Some(ObjectReader {
             archive: self,
             object_info: info,
-            buf: Buffer::with_capacity(1024 * 1024),
+            buf: Buffer::with_capacity(chunk_buffer_size),
             object_epilogue: None,
-            decompressor: Decompressor::new(),
+            keyed_hasher,
         }))
     }
 
+    pub fn list_objects(&mut self) -> Result<&[ObjectInfo], Error> {
+        while self.manifest.is_none() {
+            let (chunk_type, chunk) = self.read_chunk()?;
+            if chunk_type == ChunkType::End {
+                self.manifest = Some(Manifest::from_slice(&chunk)?);
+            }
+        }
+        Ok(&self.manifest.as_ref().unwrap().objects)
+    }
+
+    pub fn into_manifest(mut self) -> Result<Manifest, Error> {
+        self.list_objects()?;
+        Ok(self.manifest.take().unwrap())
+    }
+
     fn open_next_volume(&mut self) -> Result<(), Error> {
-        let mut filename = self
-            .raw_path
-            .file_name()
-            .ok_or_else(|| err_msg("Error getting filename component"))?
-            .to_str()
-            .ok_or_else(|| err_msg("Error decoding filename"))?
-            .to_owned();
-        ensure!(filename.ends_with(".001"), "Invalid filename");
-        if self.volume_counter.is_none() {
-            self.volume_counter = Some(1);
-        }
-        self.volume_counter = Some(self.volume_counter.unwrap() + 1);
-        filename.truncate(filename.len() - 4);
-        filename.push_str(&format!(".{:03}", self.volume_counter.unwrap()));
-        self.file = File::open(&self.raw_path.with_file_name(filename))
-            .context("Error opening next volume")?;
+        ensure!(
+            self.is_multi_volume,
+            errors::Error::format("Unexpected volume end in a single-volume archive")
+        );
+        let volume_provider = self
+            .volume_provider
+            .as_mut()
+            .ok_or_else(|| errors::Error::not_found("Archive has no more volumes"))?;
+        self.volume_counter += 1;
+        self.file = volume_provider(self.volume_counter)?;
         Ok(())
     }
 
     pub fn read_chunk(&mut self) -> Result<(ChunkType, Vec<u8>), Error> {
-        let mut encrypted_info = [0u8; 1 + size_of::<u32>() + secretstream::ADDITIONAL_BYTES];
-        self.file.read_exact(&mut encrypted_info)?;
-        let info = self
-            .puller
-            .pull(&encrypted_info, None)
-            .context("Error decrypting chunk info")?;
-        let chunk_type = ChunkType::try_from(info[0]).unwrap();
-        let clen = BigEndian::read_u32(&info[1..]);
-        let mut ciphertext = vec![0u8; clen as usize];
-        self.file.read_exact(&mut ciphertext)?;
-        let chunk = self
-            .puller
-            .pull(&ciphertext, None)
-            .context("Error decrypting chunk data")?;
+        let (info, chunk) = read_encrypted_chunk(&mut self.file, &mut self.puller)?;
+        let chunk_type = ChunkType::try_from(info[0])?;
+        if chunk_type == ChunkType::Header {
+            self.header_count += 1;
+        }
+        if chunk_type == ChunkType::Signature {
+            ensure!(
+                self.signature.is_none() && self.verifier.is_some(),
+                errors::Error::format("Unexpected signature chunk")
+            );
+            self.signature = Some(chunk);
+            return self.read_chunk();
+        }
+        ensure!(
+            self.signature.is_none() || chunk_type == ChunkType::End,
+            errors::Error::format("Archive signature must precede the manifest")
+        );
+        if self.signature.is_none() {
+            if let Some(verifier) = self.verifier.as_mut() {
+                verifier.update(&info);
+                verifier.update(&chunk);
+            }
+        }
+        if chunk_type == ChunkType::ArchiveMetadata {
+            self.metadata = Some(serde_json::from_slice(&chunk)?);
+            return self.read_chunk();
+        }
+        if chunk_type == ChunkType::End {
+            self.verify_stream_signature()?;
+        }
         if chunk_type == ChunkType::VolumeEnd {
             self.open_next_volume()?;
             return self.read_chunk();
@@ -313,37 +1746,108 @@ impl ArchiveReader {
     }
 }
 
-pub struct ObjectReader<'a> {
-    archive: &'a mut ArchiveReader,
-    pub object_info: ObjectInfo,
+pub struct ObjectReader<'a, R: Read> {
+    archive: &'a mut ArchiveReader<R>,
+    object_info: ObjectInfo,
     buf: Buffer,
-    pub object_epilogue: Option<ObjectEpilogue>,
-    decompressor: Decompressor,
+    object_epilogue: Option<ObjectEpilogue>,
+    keyed_hasher: Hasher,
 }
 
-impl ObjectReader<'_> {
+impl<R: Read> ObjectReader<'_, R> {
+    pub fn metadata(&self) -> &ObjectInfo {
+        &self.object_info
+    }
+
+    pub fn name(&self) -> &str {
+        &self.object_info.name
+    }
+
+    pub fn path(&self) -> &[String] {
+        &self.object_info.path
+    }
+
+    pub fn object_type(&self) -> ObjectType {
+        self.object_info.object_type
+    }
+
+    pub fn epilogue(&self) -> Option<&ObjectEpilogue> {
+        self.object_epilogue.as_ref()
+    }
+
     pub fn read_data(&mut self) -> Result<Option<Vec<u8>>, Error> {
         let (part_type, part) = self.archive.read_chunk()?;
         match part_type {
+            ChunkType::Data if !self.object_info.compressed => {
+                self.keyed_hasher.update(&part);
+                Ok(Some(part))
+            }
             ChunkType::Data => {
                 let data = self
+                    .archive
                     .decompressor
                     .decompress(&part)
                     .context("Error decompressing data")?;
+                self.keyed_hasher.update(&data);
                 Ok(Some(data.to_vec()))
             }
             ChunkType::Epilogue => {
-                self.object_epilogue = Some(serde_json::from_slice(&part)?);
+                ensure!(
+                    !self.object_info.compressed || self.archive.decompressor.frame_ended(),
+                    errors::Error::format(format!(
+                        "Compressed data of {} ends inside a zstd frame",
+                        self.object_info.path.join("/")
+                    ))
+                );
+                let epilogue: ObjectEpilogue = serde_json::from_slice(&part)?;
+                if let Some(keyed_hash) = epilogue.keyed_hash.as_ref() {
+                    ensure!(
+                        encoding::to_hex(&self.keyed_hasher.finalize()) == *keyed_hash,
+                        errors::Error::crypto(format!(
+                            "Keyed hash mismatch for {}",
+                            self.object_info.path.join("/")
+                        ))
+                    );
+                }
+                self.object_epilogue = Some(epilogue);
                 Ok(None)
             }
-            _ => Err(format_err!("Unexpected part type: {:?}", part_type)),
+            _ => {
+                Err(errors::Error::format(format!("Unexpected part type: {:?}", part_type)).into())
+            }
         }
     }
+
+    pub fn skip(&mut self) -> Result<(), Error> {
+        if self.object_info.object_type != ObjectType::File {
+            return Ok(());
+        }
+        while self.object_epilogue.is_none() {
+            let (part_type, part) = self.archive.read_chunk()?;
+            match part_type {
+                ChunkType::Data => continue,
+                ChunkType::Epilogue => {
+                    self.object_epilogue = Some(serde_json::from_slice(&part)?);
+                }
+                _ => {
+                    return Err(errors::Error::format(format!(
+                        "Unexpected part type: {:?}",
+                        part_type
+                    ))
+                    .into())
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
-impl Read for ObjectReader<'_> {
+impl<R: Read> Read for ObjectReader<'_, R> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
-        if buf.is_empty() || self.object_epilogue.is_some() {
+        if buf.is_empty()
+            || self.object_epilogue.is_some()
+            || self.object_info.object_type != ObjectType::File
+        {
             return Ok(0);
         }
         if !self.buf.is_empty() {
@@ -367,3 +1871,472 @@ impl Read for ObjectReader<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{
+        check_volume_sequence, ArchiveReader, ArchiveWriter, Manifest, COMPRESSION_NONE, MEMLIMIT,
+        OPSLIMIT,
+    };
+    use crate::errors;
+    use crate::errors::ErrorKind;
+    use crate::sodium;
+    use crate::sodium::pwhash::PwhashAlgorithm;
+    use crate::sodium::signing::Keypair;
+    use crate::utils;
+    use std::fs;
+    use std::io;
+    use std::io::{Cursor, Read};
+    use std::time::Duration;
+
+    #[test]
+    fn roundtrip_test() {
+        sodium::init().unwrap();
+        let mut buf = Vec::new();
+        {
+            let mut writer = ArchiveWriter::from_writer(&mut buf, "password", None).unwrap();
+            writer
+                .write_bytes(b"Hello, world!", &["hello.txt".to_owned()], "hello.txt")
+                .unwrap();
+            writer.end().unwrap();
+        }
+        let reader = ArchiveReader::from_reader(Cursor::new(&buf), "password").unwrap();
+        let manifest = reader.into_manifest().unwrap();
+        assert_eq!(manifest.file_count(), 1);
+        assert_eq!(manifest.objects[0].original_path, "<stdin>");
+        let mut reader = ArchiveReader::from_reader(Cursor::new(buf), "password").unwrap();
+        let mut object = reader.read_object().unwrap().unwrap();
+        assert_eq!(object.path(), ["hello.txt"]);
+        let mut content = Vec::new();
+        object.read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"Hello, world!");
+        assert!(reader.read_object().unwrap().is_none());
+    }
+
+    #[test]
+    fn manifest_signature_test() {
+        sodium::init().unwrap();
+        let keypair = Keypair::generate().unwrap();
+        let other = Keypair::generate().unwrap();
+        let dir = utils::test_dir("manifest_signature_test");
+        let path = dir.join("archive");
+        for mac in &[false, true] {
+            {
+                let mut writer = ArchiveWriter::new(&path, "password", None, None).unwrap();
+                if *mac {
+                    writer = writer.with_hmac();
+                }
+                writer
+                    .write_bytes(b"Hello, world!", &["hello.txt".to_owned()], "hello.txt")
+                    .unwrap();
+                writer.end_signed(&keypair.private_key).unwrap();
+            }
+            assert!(ArchiveReader::verify_signature_at(&path, &keypair.public_key).unwrap());
+            assert!(!ArchiveReader::verify_signature_at(&path, &other.public_key).unwrap());
+            let mut reader = ArchiveReader::new(&path, "password").unwrap();
+            assert!(reader.verify_signature(&keypair.public_key).unwrap());
+            assert!(!reader.verify_signature(&other.public_key).unwrap());
+            if *mac {
+                assert!(reader.verify_hmac().unwrap());
+            }
+            assert_eq!(reader.list_objects().unwrap().len(), 1);
+        }
+        {
+            let mut writer = ArchiveWriter::new(&path, "password", None, None).unwrap();
+            writer.end().unwrap();
+        }
+        let err = ArchiveReader::verify_signature_at(&path, &keypair.public_key).unwrap_err();
+        assert!(matches!(errors::kind_of(&err), Some(ErrorKind::NotFound)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keyed_hash_test() {
+        sodium::init().unwrap();
+        let mut buf = Vec::new();
+        {
+            let mut writer = ArchiveWriter::from_writer(&mut buf, "password", None)
+                .unwrap()
+                .with_keyed_hashes();
+            for name in &["a.txt", "b.txt"] {
+                writer
+                    .write_bytes(b"Hello, world!", &[name.to_string()], name)
+                    .unwrap();
+            }
+            writer.end().unwrap();
+        }
+        let mut reader = ArchiveReader::from_reader(Cursor::new(buf), "password").unwrap();
+        let mut keyed_hashes = Vec::new();
+        while let Some(mut object) = reader.read_object().unwrap() {
+            let mut content = Vec::new();
+            object.read_to_end(&mut content).unwrap();
+            assert_eq!(content, b"Hello, world!");
+            keyed_hashes.push(object.epilogue().unwrap().keyed_hash.clone().unwrap());
+        }
+        assert_eq!(keyed_hashes.len(), 2);
+        assert_ne!(keyed_hashes[0], keyed_hashes[1]);
+    }
+
+    struct FlakyReader {
+        failures: usize,
+        kind: io::ErrorKind,
+        data: &'static [u8],
+    }
+
+    impl Read for FlakyReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(io::Error::from(self.kind));
+            }
+            self.data.read(buf)
+        }
+    }
+
+    #[test]
+    fn read_retries_test() {
+        sodium::init().unwrap();
+        let mut writer = ArchiveWriter::from_writer(Vec::new(), "password", None)
+            .unwrap()
+            .with_read_retries(2);
+        let mut reader = FlakyReader {
+            failures: 2,
+            kind: io::ErrorKind::Interrupted,
+            data: b"Hello",
+        };
+        writer
+            .write_object_from_reader(&mut reader, "a", &["a".to_owned()])
+            .unwrap();
+        let mut reader = FlakyReader {
+            failures: 3,
+            kind: io::ErrorKind::Interrupted,
+            data: b"Hello",
+        };
+        assert!(writer
+            .write_object_from_reader(&mut reader, "b", &["b".to_owned()])
+            .is_err());
+    }
+
+    #[test]
+    fn would_block_timeout_test() {
+        sodium::init().unwrap();
+        let mut writer = ArchiveWriter::from_writer(Vec::new(), "password", None)
+            .unwrap()
+            .with_read_retries(0)
+            .with_would_block_timeout(Duration::from_millis(200));
+        let mut reader = FlakyReader {
+            failures: 2,
+            kind: io::ErrorKind::WouldBlock,
+            data: b"Hello",
+        };
+        writer
+            .write_object_from_reader(&mut reader, "a", &["a".to_owned()])
+            .unwrap();
+        let mut reader = FlakyReader {
+            failures: usize::MAX,
+            kind: io::ErrorKind::WouldBlock,
+            data: b"Hello",
+        };
+        assert!(writer
+            .write_object_from_reader(&mut reader, "b", &["b".to_owned()])
+            .is_err());
+    }
+
+    #[test]
+    fn format_version_test() {
+        sodium::init().unwrap();
+        let mut buf = Vec::new();
+        {
+            let mut writer = ArchiveWriter::from_writer(&mut buf, "password", None).unwrap();
+            writer.end().unwrap();
+        }
+        assert_eq!(&buf[..8], b"SECR\x00\x01\x00\x00");
+        buf[7] = 1;
+        ArchiveReader::from_reader(Cursor::new(&buf), "password")
+            .unwrap()
+            .into_manifest()
+            .unwrap();
+        buf[5] = 2;
+        let err = ArchiveReader::from_reader(Cursor::new(&buf), "password")
+            .err()
+            .unwrap();
+        assert!(matches!(errors::kind_of(&err), Some(ErrorKind::Format)));
+    }
+
+    #[test]
+    fn truncated_archive_test() {
+        sodium::init().unwrap();
+        let mut buf = Vec::new();
+        {
+            let mut writer = ArchiveWriter::from_writer(&mut buf, "password", None).unwrap();
+            writer
+                .write_bytes(b"Hello, world!", &["hello.txt".to_owned()], "hello.txt")
+                .unwrap();
+            writer.end().unwrap();
+        }
+        buf.truncate(buf.len() - 100);
+        let mut reader = ArchiveReader::from_reader(Cursor::new(&buf), "password").unwrap();
+        let err = reader.list_objects().unwrap_err();
+        assert!(matches!(errors::kind_of(&err), Some(ErrorKind::Io(_))));
+        let err = ArchiveReader::from_reader(Cursor::new(&buf[..6]), "password")
+            .err()
+            .unwrap();
+        assert!(matches!(errors::kind_of(&err), Some(ErrorKind::Io(_))));
+    }
+
+    #[test]
+    fn manifest_version_test() {
+        let manifest = Manifest::from_slice(br#"{"objects":[]}"#).unwrap();
+        assert_eq!(manifest.version, 1);
+        assert!(Manifest::from_slice(br#"{"version":2,"objects":[]}"#).is_err());
+    }
+
+    #[test]
+    fn compression_level_test() {
+        sodium::init().unwrap();
+        assert!(ArchiveWriter::from_writer(Vec::new(), "password", Some(23)).is_err());
+        assert!(ArchiveWriter::from_writer(Vec::new(), "password", Some(-101)).is_err());
+        assert!(ArchiveWriter::from_writer(Vec::new(), "password", Some(-100)).is_ok());
+        let mut buf = Vec::new();
+        {
+            let mut writer =
+                ArchiveWriter::from_writer(&mut buf, "password", Some(COMPRESSION_NONE)).unwrap();
+            writer
+                .write_bytes(b"Hello, world!", &["hello.txt".to_owned()], "hello.txt")
+                .unwrap();
+            writer.end().unwrap();
+        }
+        let mut reader = ArchiveReader::from_reader(Cursor::new(buf), "password").unwrap();
+        let mut object = reader.read_object().unwrap().unwrap();
+        assert!(!object.metadata().compressed);
+        let mut content = Vec::new();
+        object.read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"Hello, world!");
+    }
+
+    #[test]
+    fn estimate_output_size_test() {
+        sodium::init().unwrap();
+        let dir = utils::test_dir("estimate_output_size_test");
+        fs::write(dir.join("random"), sodium::randombytes(300 * 1024)).unwrap();
+        fs::write(dir.join("zeros"), vec![0u8; 300 * 1024]).unwrap();
+        let estimate = ArchiveWriter::estimate_output_size(&[dir.clone()]).unwrap();
+        let mut buf = Vec::new();
+        {
+            let mut writer = ArchiveWriter::from_writer(&mut buf, "password", None).unwrap();
+            for name in &["random", "zeros"] {
+                writer
+                    .write_object(dir.join(name), &[name.to_string()])
+                    .unwrap();
+            }
+            writer.end().unwrap();
+        }
+        assert!(estimate >= buf.len() as u64);
+        assert!(estimate < 600 * 1024);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn drop_discards_test() {
+        sodium::init().unwrap();
+        let dir = utils::test_dir("drop_discards_test");
+        let path = dir.join("archive");
+        {
+            let mut writer = ArchiveWriter::new(&path, "password", None, None).unwrap();
+            writer
+                .write_bytes(b"Hello, world!", &["hello.txt".to_owned()], "hello.txt")
+                .unwrap();
+        }
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        {
+            let mut writer = ArchiveWriter::new(&path, "password", None, None).unwrap();
+            writer
+                .write_bytes(b"Hello, world!", &["hello.txt".to_owned()], "hello.txt")
+                .unwrap();
+            writer.end().unwrap();
+        }
+        assert!(path.exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directory_count_test() {
+        sodium::init().unwrap();
+        let dir = utils::test_dir("directory_count_test");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("top.txt"), b"top").unwrap();
+        fs::write(dir.join("sub").join("nested.txt"), b"nested").unwrap();
+        let mut buf = Vec::new();
+        {
+            let mut writer = ArchiveWriter::from_writer(&mut buf, "password", None).unwrap();
+            for path in utils::generate_tree_iter(&dir, false) {
+                let path = path.unwrap();
+                let object_path: Vec<String> = path
+                    .strip_prefix(dir.parent().unwrap())
+                    .unwrap()
+                    .iter()
+                    .map(|component| component.to_str().unwrap().to_owned())
+                    .collect();
+                writer.write_object(&path, &object_path).unwrap();
+            }
+            writer.end().unwrap();
+        }
+        let manifest = ArchiveReader::from_reader(Cursor::new(&buf), "password")
+            .unwrap()
+            .into_manifest()
+            .unwrap();
+        assert_eq!(manifest.directory_count(), 2);
+        assert_eq!(manifest.file_count(), 2);
+        let mut copy = Vec::new();
+        {
+            let mut input = ArchiveReader::from_reader(Cursor::new(&buf), "password").unwrap();
+            let mut writer = ArchiveWriter::from_writer(&mut copy, "password", None).unwrap();
+            writer.copy_from(&mut input).unwrap();
+            writer.end().unwrap();
+        }
+        let manifest = ArchiveReader::from_reader(Cursor::new(copy), "password")
+            .unwrap()
+            .into_manifest()
+            .unwrap();
+        assert_eq!(manifest.directory_count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hmac_test() {
+        sodium::init().unwrap();
+        let dir = utils::test_dir("hmac_test");
+        let path = dir.join("archive");
+        for hmac in &[true, false] {
+            {
+                let mut writer = ArchiveWriter::new(&path, "password", None, None).unwrap();
+                if *hmac {
+                    writer = writer.with_hmac();
+                }
+                writer
+                    .write_bytes(b"Hello, world!", &["hello.txt".to_owned()], "hello.txt")
+                    .unwrap();
+                writer.end().unwrap();
+            }
+            let reader = ArchiveReader::new(&path, "password").unwrap();
+            if *hmac {
+                assert!(reader.verify_hmac().unwrap());
+            } else {
+                let err = reader.verify_hmac().unwrap_err();
+                assert_eq!(err.to_string(), "Archive has no MAC");
+                assert!(matches!(errors::kind_of(&err), Some(ErrorKind::NotFound)));
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reencrypt_test() {
+        sodium::init().unwrap();
+        let dir = utils::test_dir("reencrypt_test");
+        let (input, output) = (dir.join("input"), dir.join("output"));
+        {
+            let mut writer =
+                ArchiveWriter::new_with_alg(&input, "old", PwhashAlgorithm::Argon2i, None, None)
+                    .unwrap()
+                    .with_hmac()
+                    .with_keyed_hashes();
+            writer
+                .write_bytes(b"Hello, world!", &["hello.txt".to_owned()], "hello.txt")
+                .unwrap();
+            writer.end().unwrap();
+        }
+        ArchiveWriter::reencrypt(&input, "old", "new", &output, None).unwrap();
+        let mut reader = ArchiveReader::new(&output, "new").unwrap();
+        assert!(reader.verify_hmac().unwrap());
+        assert_eq!(
+            reader.pwhash_params.as_ref().unwrap().algorithm,
+            PwhashAlgorithm::Argon2i
+        );
+        let mut object = reader.read_object().unwrap().unwrap();
+        let mut content = Vec::new();
+        object.read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"Hello, world!");
+        assert!(object.epilogue().unwrap().keyed_hash.is_some());
+        fs::remove_file(&output).unwrap();
+        {
+            let keypair = Keypair::generate().unwrap();
+            let mut writer = ArchiveWriter::new(&input, "old", None, None).unwrap();
+            writer.end_signed(&keypair.private_key).unwrap();
+        }
+        let err = ArchiveWriter::reencrypt(&input, "old", "new", &output, None).unwrap_err();
+        assert!(matches!(
+            errors::kind_of(&err),
+            Some(ErrorKind::InvalidArgument)
+        ));
+        assert!(!output.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn check_password_test() {
+        sodium::init().unwrap();
+        let dir = utils::test_dir("check_password_test");
+        let path = dir.join("archive");
+        {
+            let mut writer =
+                ArchiveWriter::from_writer(fs::File::create(&path).unwrap(), "password", None)
+                    .unwrap();
+            writer.end().unwrap();
+        }
+        assert!(ArchiveReader::check_password(&path, "password").unwrap());
+        let reader = ArchiveReader::new(&path, "password").unwrap();
+        let params = reader.pwhash_params().unwrap();
+        assert_eq!(params.algorithm, PwhashAlgorithm::Argon2id);
+        assert_eq!((params.opslimit, params.memlimit), (OPSLIMIT, MEMLIMIT));
+        assert!(!ArchiveReader::check_password(&path, "wrong").unwrap());
+        let mut reader = ArchiveReader::new(&path, "wrong").unwrap();
+        let err = reader.read_chunk().unwrap_err();
+        assert!(matches!(errors::kind_of(&err), Some(ErrorKind::Crypto)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn volume_size_test() {
+        sodium::init().unwrap();
+        let dir = utils::test_dir("volume_size_test");
+        let volume_size = 300 * 1024;
+        let data = sodium::randombytes(2 * 1024 * 1024);
+        {
+            let mut writer =
+                ArchiveWriter::new(dir.join("archive"), "password", None, Some(volume_size))
+                    .unwrap()
+                    .with_read_buffer_size(256 * 1024);
+            writer
+                .write_bytes(&data, &["random".to_owned()], "random")
+                .unwrap();
+            writer.end().unwrap();
+        }
+        let volumes = fs::read_dir(&dir).unwrap().count();
+        assert!(volumes > 2);
+        for entry in fs::read_dir(&dir).unwrap() {
+            assert!(entry.unwrap().metadata().unwrap().len() <= volume_size);
+        }
+        let mut reader = ArchiveReader::new(dir.join("archive.001"), "password").unwrap();
+        let mut object = reader.read_object().unwrap().unwrap();
+        let mut content = Vec::new();
+        object.read_to_end(&mut content).unwrap();
+        assert_eq!(content, data);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn volume_sequence_test() {
+        let dir = utils::test_dir("volume_sequence_test");
+        for counter in &[1, 2, 4] {
+            fs::write(dir.join(format!("archive.{:03}", counter)), b"").unwrap();
+        }
+        let err = check_volume_sequence(&dir.join("archive.001")).unwrap_err();
+        assert_eq!(err.to_string(), "Missing volumes: 003");
+        assert!(matches!(errors::kind_of(&err), Some(ErrorKind::NotFound)));
+        fs::write(dir.join("archive.003"), b"").unwrap();
+        check_volume_sequence(&dir.join("archive.001")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
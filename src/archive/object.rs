@@ -2,11 +2,23 @@ use serde;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::io;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 pub enum ObjectType {
     File,
     Directory,
+    Hardlink,
+}
+
+impl ObjectType {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ObjectType::Directory => "directory",
+            ObjectType::File => "file",
+            ObjectType::Hardlink => "hardlink",
+        }
+    }
 }
 
 impl Serialize for ObjectType {
@@ -14,10 +26,7 @@ impl Serialize for ObjectType {
     where
         S: Serializer,
     {
-        serializer.serialize_str(match *self {
-            ObjectType::Directory => "directory",
-            ObjectType::File => "file",
-        })
+        serializer.serialize_str(self.as_str())
     }
 }
 
@@ -30,6 +39,7 @@ impl<'de> Deserialize<'de> for ObjectType {
         String::deserialize(deserializer).and_then(|string| match string.as_str() {
             "directory" => Ok(ObjectType::Directory),
             "file" => Ok(ObjectType::File),
+            "hardlink" => Ok(ObjectType::Hardlink),
             _ => Err(Error::custom("Not a valid object type")),
         })
     }
@@ -43,6 +53,20 @@ pub struct ObjectInfo {
     pub path: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub epilogue: Option<ObjectEpilogue>,
+    #[serde(default = "default_compressed")]
+    pub compressed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_target: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_at: Option<u64>,
+}
+
+fn default_compressed() -> bool {
+    true
 }
 
 impl Clone for ObjectInfo {
@@ -53,6 +77,11 @@ impl Clone for ObjectInfo {
             original_path: self.original_path.clone(),
             path: self.path.clone(),
             epilogue: self.epilogue.clone(),
+            compressed: self.compressed,
+            uid: self.uid,
+            gid: self.gid,
+            link_target: self.link_target.clone(),
+            archived_at: self.archived_at,
         }
     }
 }
@@ -61,6 +90,10 @@ impl Clone for ObjectInfo {
 pub struct ObjectEpilogue {
     pub size: u64,
     pub hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compressed_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyed_hash: Option<String>,
 }
 
 impl ObjectInfo {
@@ -71,6 +104,8 @@ impl ObjectInfo {
         let name = path.file_name().unwrap().to_str().unwrap().to_string();
         let original_path = real_path.to_str().unwrap().to_string();
         let object_path = object_path.to_vec();
+        let (uid, gid) = ownership(&metadata);
+        let archived_at = now();
         if metadata.is_dir() {
             Ok(Self {
                 object_type: ObjectType::Directory,
@@ -78,6 +113,11 @@ impl ObjectInfo {
                 original_path,
                 path: object_path,
                 epilogue: None,
+                compressed: true,
+                uid,
+                gid,
+                link_target: None,
+                archived_at,
             })
         } else if metadata.is_file() {
             Ok(Self {
@@ -86,6 +126,11 @@ impl ObjectInfo {
                 original_path,
                 path: object_path,
                 epilogue: None,
+                compressed: true,
+                uid,
+                gid,
+                link_target: None,
+                archived_at,
             })
         } else {
             Err(io::Error::new(
@@ -94,4 +139,63 @@ impl ObjectInfo {
             ))
         }
     }
+
+    #[cfg(unix)]
+    pub fn restore_ownership<P: AsRef<Path>>(&self, path: P) -> Result<(), io::Error> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        if self.uid.is_none() && self.gid.is_none() {
+            return Ok(());
+        }
+        let path = CString::new(path.as_ref().as_os_str().as_bytes())?;
+        let uid = self.uid.unwrap_or(u32::MAX) as libc::uid_t;
+        let gid = self.gid.unwrap_or(u32::MAX) as libc::gid_t;
+        if unsafe { libc::chown(path.as_ptr(), uid, gid) } != 0 {
+            let err = io::Error::last_os_error();
+            // Without CAP_CHOWN, files are left owned by the extracting user.
+            if err.raw_os_error() != Some(libc::EPERM) {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn restore_ownership<P: AsRef<Path>>(&self, _path: P) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+pub fn now() -> Option<u64> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|duration| duration.as_secs())
+}
+
+#[cfg(unix)]
+pub fn hardlink_id<P: AsRef<Path>>(path: P) -> Result<Option<(u64, u64)>, io::Error> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = std::fs::symlink_metadata(path)?;
+    if metadata.is_file() && metadata.nlink() > 1 {
+        Ok(Some((metadata.dev(), metadata.ino())))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(not(unix))]
+pub fn hardlink_id<P: AsRef<Path>>(_path: P) -> Result<Option<(u64, u64)>, io::Error> {
+    Ok(None)
+}
+
+#[cfg(unix)]
+fn ownership(metadata: &std::fs::Metadata) -> (Option<u32>, Option<u32>) {
+    use std::os::unix::fs::MetadataExt;
+    (Some(metadata.uid()), Some(metadata.gid()))
+}
+
+#[cfg(not(unix))]
+fn ownership(_metadata: &std::fs::Metadata) -> (Option<u32>, Option<u32>) {
+    (None, None)
 }
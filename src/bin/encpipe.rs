@@ -7,11 +7,14 @@ use std::io::prelude::*;
 use std::io::BufReader;
 use std::mem::size_of;
 
+use anyhow::{ensure, Context, Error};
 use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
-use failure::{err_msg, Error, ResultExt};
 use serde::{Deserialize, Serialize};
 
-use secrets::{parsing, sodium, utils};
+use secrets::errors::ErrorChain;
+use secrets::key::{Key, PublicKey};
+use secrets::parsing::Arguments;
+use secrets::{encoding, parsing, sodium, utils};
 
 #[derive(Serialize, Deserialize)]
 struct Epilogue {
@@ -31,21 +34,25 @@ fn write_chunk(
         (data.len() + sodium::secretstream::ADDITIONAL_BYTES) as u32,
     );
     info[0] = chunk_type;
-    let enc_info = stream.push(&info, None).unwrap();
+    let enc_info = stream.push(&info)?;
     output
         .write_all(&enc_info)
         .context("Error writing chunk info")?;
-    let enc_data = stream.push(data, None).unwrap();
+    let enc_data = stream.push(data)?;
     output
         .write_all(&enc_data)
         .context("Error writing chunk data")?;
     Ok(())
 }
 
+const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
+const MIN_CHUNK_SIZE: usize = 4 * 1024;
+
 fn encrypt_file(
     input: &mut dyn BufRead,
     output: &mut dyn Write,
     password: &str,
+    chunk_size: usize,
 ) -> Result<Epilogue, Error> {
     let salt = sodium::randombytes(sodium::pwhash::SALT_BYTES);
     let opslimit = 3;
@@ -58,13 +65,36 @@ fn encrypt_file(
         memlimit,
     )
     .context("Error deriving key from password")?;
-    let mut stream = sodium::secretstream::SecretStream::new_push(&key).unwrap();
     output.write_all(&salt).context("Error writing salt")?;
     output.write_u64::<BigEndian>(opslimit)?;
     output.write_u64::<BigEndian>(memlimit as u64)?;
+    encrypt_stream(input, output, &key, chunk_size)
+}
+
+fn encrypt_file_to(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    public_key: &PublicKey,
+    chunk_size: usize,
+) -> Result<Epilogue, Error> {
+    let key = sodium::randombytes(sodium::secretstream::KEY_BYTES);
+    let sealed_key = sodium::crypto_box::sealed_box_encrypt(&key, &public_key.box_pk)?;
+    output
+        .write_all(&sealed_key)
+        .context("Error writing sealed key")?;
+    encrypt_stream(input, output, &key, chunk_size)
+}
+
+fn encrypt_stream(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    key: &[u8],
+    chunk_size: usize,
+) -> Result<Epilogue, Error> {
+    let mut stream = sodium::secretstream::SecretStream::new_push(key)?;
     output.write_all(&stream.get_header())?;
     let mut hasher = sodium::hashing::Hasher::new();
-    let mut buf = vec![0u8; 1024 * 256];
+    let mut buf = vec![0u8; chunk_size];
     let mut size = 0u64;
     loop {
         let count = input.read(&mut buf).context("Error reading from input")?;
@@ -76,7 +106,7 @@ fn encrypt_file(
         size += count as u64;
     }
     let epilogue = Epilogue {
-        hash: sodium::to_hex(hasher.finalize().as_slice()),
+        hash: encoding::to_hex(hasher.finalize().as_slice()),
         size,
     };
     write_chunk(&mut stream, output, &serde_json::to_vec(&epilogue)?, 1)
@@ -90,12 +120,12 @@ fn read_chunk(
 ) -> Result<(Vec<u8>, u8), Error> {
     let mut enc_info = vec![0u8; size_of::<u32>() + 1 + sodium::secretstream::ADDITIONAL_BYTES];
     input.read_exact(&mut enc_info)?;
-    let info = stream.pull(&enc_info, None)?;
+    let info = stream.pull(&enc_info)?;
     let chunk_type = info[0];
     let size = BigEndian::read_u32(&info[1..]);
     let mut enc_data = vec![0u8; size as usize];
     input.read_exact(&mut enc_data)?;
-    let data = stream.pull(&enc_data, None)?;
+    let data = stream.pull(&enc_data)?;
     Ok((data, chunk_type))
 }
 
@@ -116,53 +146,116 @@ fn decrypt_file(
         memlimit,
     )
     .context("Error deriving key from password")?;
+    decrypt_stream(input, output, &key)
+}
+
+fn decrypt_file_with(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    secret_key: &Key,
+) -> Result<Epilogue, Error> {
+    let mut sealed_key =
+        vec![0u8; sodium::secretstream::KEY_BYTES + sodium::crypto_box::seal_bytes()];
+    input.read_exact(&mut sealed_key)?;
+    let keypair = secret_key.box_keypair();
+    let key = sodium::crypto_box::sealed_box_decrypt(&sealed_key, &keypair.pk, &keypair.sk)
+        .context("Error unsealing session key")?;
+    decrypt_stream(input, output, &key)
+}
+
+fn decrypt_stream(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    key: &[u8],
+) -> Result<Epilogue, Error> {
     let mut header = vec![0u8; sodium::secretstream::HEADER_BYTES];
     input.read_exact(&mut header)?;
-    let mut stream = sodium::secretstream::SecretStream::new_pull(&header, &key).unwrap();
+    let mut stream = sodium::secretstream::SecretStream::new_pull(&header, key)?;
     let mut hasher = sodium::hashing::Hasher::new();
-    let mut epilogue: Option<Epilogue> = None;
-    loop {
+    let epilogue: Epilogue = loop {
         let (chunk, chunk_type) = read_chunk(&mut stream, input)?;
         if chunk_type == 1 {
-            epilogue = Some(serde_json::from_slice(&chunk)?);
-            break;
+            break serde_json::from_slice(&chunk).context("Error parsing epilogue")?;
         }
         output.write_all(&chunk)?;
         hasher.update(&chunk);
+    };
+    ensure!(
+        encoding::to_hex(&hasher.finalize()) == epilogue.hash,
+        "Hash mismatch"
+    );
+    Ok(epilogue)
+}
+
+fn run(args: &Arguments) -> Result<(), Error> {
+    sodium::init()?;
+    ensure!(
+        !(args.flags.contains_key("encrypt") && args.flags.contains_key("decrypt")),
+        "Invalid operation"
+    );
+    let mut input: Box<dyn BufRead> = match args.get_required("input")? {
+        "-" => Box::new(BufReader::new(io::stdin())),
+        path @ _ => Box::new(BufReader::new(
+            File::open(path).context("Error opening input file")?,
+        )),
+    };
+    let mut outputs: Vec<Box<dyn Write>> = Vec::new();
+    for path in &args.multi_flags["output"] {
+        outputs.push(match path.as_str() {
+            "-" => Box::new(io::stdout()),
+            path @ _ => Box::new(File::create(path).context("Error creating output file")?),
+        });
+    }
+    let mut output = utils::MultiWriter::new(outputs);
+    let chunk_size = match args.get_optional("chunk-size") {
+        Some(size) => utils::parse_size(size).context("Error parsing chunk size")? as usize,
+        None => DEFAULT_CHUNK_SIZE,
+    };
+    ensure!(chunk_size > 0, "Chunk size must be positive");
+    if chunk_size < MIN_CHUNK_SIZE {
+        eprintln!(
+            "Warning: chunk size below {} bytes adds high overhead from AEAD tags and chunk framing",
+            MIN_CHUNK_SIZE
+        );
     }
-    if sodium::to_hex(&hasher.finalize()) != epilogue.as_ref().unwrap().hash {
-        panic!("Hash mismatch");
+    if args.flags.contains_key("encrypt") {
+        if let Some(path) = args.get_optional("pubkey") {
+            let public_key = PublicKey::load_from_file(path)?;
+            encrypt_file_to(input.as_mut(), &mut output, &public_key, chunk_size)?;
+        } else {
+            let password = utils::get_password(args)?;
+            encrypt_file(input.as_mut(), &mut output, &password, chunk_size)?;
+        }
+    } else if args.flags.contains_key("decrypt") {
+        let password = utils::get_password(args)?;
+        if let Some(path) = args.get_optional("key") {
+            let secret_key = Key::load_from_file(path, &password)?;
+            decrypt_file_with(input.as_mut(), &mut output, &secret_key)?;
+        } else {
+            decrypt_file(input.as_mut(), &mut output, &password)?;
+        }
     }
-    Ok(epilogue.unwrap())
+    output.flush().context("Error flushing output")?;
+    Ok(())
 }
 
 fn main() {
     let args_vec: Vec<String> = env::args().collect();
-    sodium::init().unwrap();
     let mut parser = parsing::Parser::new();
     parser.add_argument("encrypt", Some("e"), 0);
     parser.add_argument("decrypt", Some("d"), 0);
-    parser.add_argument("input", Some("i"), 1);
-    parser.add_argument("output", Some("o"), 1);
+    parser.add_required_argument("input", Some("i"), 1);
+    parser.add_required_multi_argument("output", Some("o"));
     parser.add_argument("passfile", Some("P"), 1);
     parser.add_argument("password", Some("p"), 1);
-    let args = parser.parse_args(&args_vec[1..]).unwrap();
-    if args.flags.contains_key("encrypt") && args.flags.contains_key("decrypt") {
-        panic!("Invalid operation");
-    }
-    let mut input: Box<dyn BufRead> = match args.flags["input"].as_ref().unwrap().as_str() {
-        "-" => Box::new(BufReader::new(io::stdin())),
-        path @ _ => Box::new(BufReader::new(File::open(path).unwrap())),
-    };
-    let mut output: Box<dyn Write> = match args.flags["output"].as_ref().unwrap().as_str() {
-        "-" => Box::new(io::stdout()),
-        path @ _ => Box::new(File::create(path).unwrap()),
-    };
-    let password = utils::get_password(&args).unwrap();
-    if args.flags.contains_key("encrypt") {
-        encrypt_file(input.as_mut(), output.as_mut(), &password).unwrap();
-    } else if args.flags.contains_key("decrypt") {
-        decrypt_file(input.as_mut(), output.as_mut(), &password).unwrap();
+    parser.add_argument("pubkey", None, 1);
+    parser.add_argument("key", Some("k"), 1);
+    parser.add_argument("chunk-size", Some("C"), 1);
+    let result = parser
+        .parse_args(&args_vec[1..])
+        .and_then(|args| run(&args));
+    if let Err(err) = result {
+        eprintln!("Error: {}", ErrorChain(err.as_ref()));
+        std::process::exit(1);
     }
-    output.as_mut().flush().unwrap();
 }
@@ -6,17 +6,20 @@ use std::io;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 
+use anyhow::{anyhow, ensure, Context, Error};
 use clap::Clap;
-use failure::{ensure, err_msg, Error, ResultExt};
+use serde_json::json;
 
-use archive::object::ObjectType;
+use archive::object::{ObjectInfo, ObjectType};
 use secrets::*;
 
-use crate::archive::{ArchiveReader, ArchiveWriter};
-use crate::sodium::to_hex;
+use crate::archive::{ArchiveReader, ArchiveWriter, Manifest};
+use crate::encoding::to_hex;
+use crate::errors::ErrorChain;
+use crate::key::{Key, PublicKey};
 use crate::utils::EmptyWriter;
 
-fn read_file_content<P: AsRef<Path>>(path: P) -> Result<String, failure::Error> {
+fn read_file_content<P: AsRef<Path>>(path: P) -> Result<String, anyhow::Error> {
     let mut content = String::new();
     File::open(path.as_ref())
         .and_then(|ref mut file| file.read_to_string(&mut content))
@@ -24,31 +27,63 @@ fn read_file_content<P: AsRef<Path>>(path: P) -> Result<String, failure::Error>
     Ok(content)
 }
 
-fn get_path_components<P: AsRef<Path>>(path: P) -> Option<Vec<String>> {
-    let mut result = Vec::new();
-    for component in path.as_ref().components() {
-        let s = component.as_os_str().to_str()?;
-        result.push(s.to_owned());
+#[cfg(feature = "prompt")]
+fn prompt_password(confirm: bool) -> Result<String, Error> {
+    ensure!(
+        atty::is(atty::Stream::Stdin),
+        "Please specify password or passfile"
+    );
+    let password =
+        rpassword::read_password_from_tty(Some("Password: ")).context("Error reading password")?;
+    if confirm {
+        let confirmation = rpassword::read_password_from_tty(Some("Confirm password: "))
+            .context("Error reading password")?;
+        ensure!(password == confirmation, "Passwords do not match");
     }
-    Some(result)
+    Ok(password)
 }
 
-fn encrypt_file(
+#[cfg(not(feature = "prompt"))]
+fn prompt_password(_confirm: bool) -> Result<String, Error> {
+    Err(anyhow!("Please specify password or passfile"))
+}
+
+fn get_password(
+    password: Option<String>,
+    password_file: Option<PathBuf>,
+    confirm: bool,
+) -> Result<String, Error> {
+    if let Some(password) = password {
+        return Ok(password);
+    }
+    if let Some(password_file) = password_file {
+        return Ok(read_file_content(password_file)?.trim().to_owned());
+    }
+    match utils::get_password_from_env()? {
+        Some(password) => Ok(password),
+        None => prompt_password(confirm),
+    }
+}
+
+fn pack_inputs<W: Write>(
+    output: &mut ArchiveWriter<W>,
     input_paths: &[String],
-    output_path: &str,
-    password: &str,
-    compression_level: Option<i32>,
-    volume_size: Option<u64>,
+    verbose: bool,
 ) -> Result<(), Error> {
-    let mut output = ArchiveWriter::new(output_path, password, compression_level, volume_size)?;
     for input_path in input_paths {
-        let input_path = Path::new(input_path);
-        for path in utils::generate_tree(&input_path, true)? {
-            let object_path = get_path_components(
+        let mut input_path = PathBuf::from(input_path);
+        // "." and ".." have no name of their own to store objects under.
+        if input_path.file_name().is_none() {
+            input_path = input_path
+                .canonicalize()
+                .context("Error resolving input path")?;
+        }
+        for path in utils::generate_tree_iter(&input_path, true) {
+            let path = path?;
+            let object_path = utils::get_path_components(
                 path.strip_prefix(&input_path.parent().unwrap())
                     .context("Error transforming path")?,
-            )
-            .ok_or_else(|| err_msg("Error converting object path"))?;
+            )?;
             println!(
                 "Packing {} as {}",
                 path.to_str().unwrap(),
@@ -60,69 +95,339 @@ fn encrypt_file(
         }
     }
     output.end()?;
+    if verbose {
+        let stats = output.compression_stats();
+        println!(
+            "Compressed {} bytes to {} bytes (ratio {:.2})",
+            stats.input_bytes, stats.output_bytes, stats.ratio
+        );
+    }
     Ok(())
 }
 
-fn decrypt_file(input_path: &str, output_path: &str, password: &str) -> Result<(), Error> {
-    let mut input = archive::ArchiveReader::new(input_path, &password)?;
+#[allow(clippy::too_many_arguments)]
+fn encrypt_file(
+    input_paths: &[String],
+    output_path: Option<&str>,
+    password: &str,
+    compression_level: Option<i32>,
+    volume_size: Option<u64>,
+    dry_run: bool,
+    hmac: bool,
+    threads: usize,
+    verbose: bool,
+) -> Result<(), Error> {
+    if dry_run {
+        let mut output = ArchiveWriter::new_dry_run(password, compression_level)?
+            .with_threads(threads)
+            .with_hardlinks();
+        if hmac {
+            output = output.with_hmac();
+        }
+        pack_inputs(&mut output, input_paths, verbose)?;
+        for object in output.objects() {
+            if let Some(epilogue) = &object.epilogue {
+                println!("{} {}", epilogue.hash, object.path.join("/"));
+            }
+        }
+        println!("Estimated output size: {} bytes", output.estimated_size());
+        return Ok(());
+    }
+    let output_path = output_path.ok_or_else(|| anyhow!("Please specify output path"))?;
+    let mut output = ArchiveWriter::new(output_path, password, compression_level, volume_size)?
+        .with_threads(threads)
+        .with_hardlinks();
+    if hmac {
+        output = output.with_hmac();
+    }
+    if let Err(err) = pack_inputs(&mut output, input_paths, verbose) {
+        return Err(output.abort_with(err));
+    }
+    Ok(())
+}
+
+fn encrypt_to(
+    input_paths: &[String],
+    output_path: &str,
+    recipient_keys: &[PathBuf],
+    compression_level: Option<i32>,
+    volume_size: Option<u64>,
+    threads: usize,
+    verbose: bool,
+) -> Result<(), Error> {
+    let mut recipients = Vec::new();
+    for path in recipient_keys {
+        recipients.push(PublicKey::load_from_file(path)?);
+    }
+    let recipients: Vec<&PublicKey> = recipients.iter().collect();
+    let mut output = ArchiveWriter::new_multi_recipient(
+        output_path,
+        &recipients,
+        compression_level,
+        volume_size,
+    )?
+    .with_threads(threads)
+    .with_hardlinks();
+    if let Err(err) = pack_inputs(&mut output, input_paths, verbose) {
+        return Err(output.abort_with(err));
+    }
+    Ok(())
+}
+
+fn unpack_archive(input: &mut ArchiveReader<File>, output_path: &str) -> Result<(), Error> {
     let output_path = Path::new(output_path).to_path_buf();
+    fs::create_dir_all(&output_path).context("Error creating output directory")?;
     loop {
         let mut reader = match input.read_object()? {
             Some(reader) => reader,
             None => break,
         };
         let mut path = output_path.clone();
-        reader
-            .object_info
-            .path
-            .iter()
-            .for_each(|part| path.push(part));
-        if reader.object_info.object_type == ObjectType::Directory {
+        reader.path().iter().for_each(|part| path.push(part));
+        if reader.object_type() == ObjectType::Directory {
             fs::create_dir_all(&path)?;
+            restore_ownership(reader.metadata(), &path);
             println!("Creating directory: {}", path.to_str().unwrap());
             continue;
         }
+        if reader.object_type() == ObjectType::Hardlink {
+            let mut target = output_path.clone();
+            reader
+                .metadata()
+                .link_target
+                .as_ref()
+                .ok_or_else(|| anyhow!("Hardlink has no target"))?
+                .iter()
+                .for_each(|part| target.push(part));
+            fs::hard_link(&target, &path).context("Error creating hardlink")?;
+            println!(
+                "Creating hardlink: {} -> {}",
+                path.to_str().unwrap(),
+                target.to_str().unwrap()
+            );
+            continue;
+        }
         let mut output_file = utils::HashingWriter::new(File::create(&path)?);
         std::io::copy(&mut reader, &mut output_file)?;
-        if to_hex(&output_file.get_hash()) != reader.object_epilogue.as_ref().unwrap().hash {
-            return Err(err_msg("File hash mismatch"));
+        if to_hex(&output_file.get_hash()) != reader.epilogue().unwrap().hash {
+            return Err(anyhow!("File hash mismatch"));
         }
-        reader.object_info.epilogue = reader.object_epilogue.clone();
         println!(
             "Creating file: {}, hash={}",
             path.to_str().unwrap(),
-            reader.object_epilogue.as_ref().unwrap().hash
+            reader.epilogue().unwrap().hash
         );
         output_file.into_inner().sync_all()?;
+        restore_ownership(reader.metadata(), &path);
     }
     Ok(())
 }
 
-fn test_file(input_path: &str, password: &str) -> Result<(), Error> {
+fn restore_ownership(info: &ObjectInfo, path: &Path) {
+    if let Err(err) = info.restore_ownership(path) {
+        eprintln!(
+            "Warning: could not restore ownership of {}: {}",
+            path.display(),
+            err
+        );
+    }
+}
+
+fn decrypt_file(input_path: &str, output_path: &str, password: &str) -> Result<(), Error> {
     let mut input = ArchiveReader::new(input_path, &password)?;
+    unpack_archive(&mut input, output_path)
+}
+
+fn decrypt_from(
+    input_path: &str,
+    output_path: &str,
+    key_path: &Path,
+    password: &str,
+) -> Result<(), Error> {
+    let key = Key::load_from_file(key_path, password)?;
+    let keypair = key.box_keypair();
+    let mut input = ArchiveReader::new_with_key_ring(input_path, &keypair.pk, &keypair.sk)?;
+    unpack_archive(&mut input, output_path)
+}
+
+fn object_entry(info: &ObjectInfo) -> serde_json::Value {
+    json!({
+        "path": info.path.join("/"),
+        "type": info.object_type.as_str(),
+        "size": info.epilogue.as_ref().map(|epilogue| epilogue.size),
+        "hash": info.epilogue.as_ref().map(|epilogue| epilogue.hash.clone()),
+        "archived_at": info.archived_at,
+    })
+}
+
+fn test_file(input_path: &str, password: &str, json: bool) -> Result<(), Error> {
+    let mut input = ArchiveReader::new(input_path, &password)?;
+    let mut entries = Vec::new();
+    let mut all_ok = true;
     loop {
         let mut reader = match input.read_object()? {
             Some(reader) => reader,
             None => break,
         };
-        println!("Name: {}", reader.object_info.name);
-        println!("Path: {}", reader.object_info.path.join("/"));
-        if reader.object_info.object_type == ObjectType::Directory {
+        if !json {
+            println!("Name: {}", reader.name());
+            println!("Path: {}", reader.path().join("/"));
+        }
+        if reader.object_type() != ObjectType::File {
+            let mut entry = object_entry(reader.metadata());
+            entry["ok"] = json!(true);
+            entries.push(entry);
             continue;
         }
         let mut writer = utils::HashingWriter::new(EmptyWriter {});
         io::copy(&mut reader, &mut writer)?;
-        let hash1 = reader.object_epilogue.as_ref().unwrap().hash.clone();
-        let hash2 = sodium::to_hex(&writer.get_hash());
+        let mut info = reader.metadata().clone();
+        info.epilogue = reader.epilogue().cloned();
+        let hash1 = reader.epilogue().unwrap().hash.clone();
+        let hash2 = to_hex(&writer.get_hash());
+        if json {
+            let mut entry = object_entry(&info);
+            entry["ok"] = json!(hash1 == hash2);
+            entries.push(entry);
+            all_ok &= hash1 == hash2;
+            continue;
+        }
         ensure!(hash1 == hash2, "Hash mismatch");
         println!("Hash: {}", &hash1);
-        println!("Size: {}", reader.object_epilogue.as_ref().unwrap().size);
+        println!("Size: {}", reader.epilogue().unwrap().size);
         println!();
     }
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json!({
+                "all_ok": all_ok,
+                "metadata": input.metadata,
+                "objects": entries,
+            }))?
+        );
+        ensure!(all_ok, "Hash mismatch");
+    } else {
+        print_metadata(&input)?;
+        print_creation_info(input.manifest.as_ref().unwrap());
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&input.manifest.unwrap())?
+        );
+    }
+    Ok(())
+}
+
+fn reencrypt_file(
+    input_path: &str,
+    output_path: &str,
+    old_password: &str,
+    new_password: Option<String>,
+    new_password_file: Option<PathBuf>,
+    volume_size: Option<u64>,
+) -> Result<(), Error> {
+    let new_password = match (new_password, new_password_file) {
+        (Some(password), _) => password,
+        (None, Some(path)) => read_file_content(path)?.trim().to_owned(),
+        (None, None) => {
+            eprintln!("Enter new password");
+            prompt_password(true)?
+        }
+    };
+    ArchiveWriter::reencrypt(
+        input_path,
+        old_password,
+        &new_password,
+        output_path,
+        volume_size,
+    )
+}
+
+fn verify_file(input_path: &str, password: &str, hmac: bool) -> Result<(), Error> {
+    let mut input = ArchiveReader::new(input_path, &password)?;
+    if hmac {
+        ensure!(input.verify_hmac()?, "Archive MAC mismatch");
+    }
+    while let Some(mut reader) = input.read_object()? {
+        if reader.object_type() != ObjectType::File {
+            continue;
+        }
+        let mut writer = utils::HashingWriter::new(EmptyWriter {});
+        io::copy(&mut reader, &mut writer)?;
+        let expected = &reader.epilogue().unwrap().hash;
+        let actual = to_hex(&writer.get_hash());
+        if *expected != actual {
+            return Err(anyhow!(
+                "Hash mismatch: {}\nExpected: {}\nActual: {}",
+                reader.path().join("/"),
+                expected,
+                actual
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn list_file(input_path: &str, password: &str, json: bool) -> Result<(), Error> {
+    let mut input = ArchiveReader::new(input_path, &password)?;
+    let objects = input.list_objects()?.to_vec();
+    if json {
+        let entries: Vec<serde_json::Value> = objects.iter().map(object_entry).collect();
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
     println!(
-        "{}",
-        serde_json::to_string_pretty(&input.manifest.unwrap())?
+        "{:<10} {:>12} {:<64} {:>12} {}",
+        "TYPE", "SIZE", "HASH", "ARCHIVED_AT", "PATH"
     );
+    for object in objects {
+        let (size, hash) = match &object.epilogue {
+            Some(epilogue) => (epilogue.size.to_string(), epilogue.hash.clone()),
+            None => ("-".to_owned(), "-".to_owned()),
+        };
+        println!(
+            "{:<10} {:>12} {:<64} {:>12} {}",
+            object.object_type.as_str(),
+            size,
+            hash,
+            object
+                .archived_at
+                .map_or("-".to_owned(), |time| time.to_string()),
+            object.path.join("/")
+        );
+    }
+    let manifest = input.manifest.as_ref().unwrap();
+    print_creation_info(manifest);
+    println!(
+        "{} files, {} directories, {} bytes{}",
+        manifest.file_count(),
+        manifest.directory_count(),
+        manifest.total_size(),
+        manifest
+            .compressed_size_estimate()
+            .map_or(String::new(), |size| format!(
+                " ({} bytes compressed)",
+                size
+            ))
+    );
+    print_metadata(&input)?;
+    Ok(())
+}
+
+fn print_creation_info(manifest: &Manifest) {
+    if let Some(created_at) = manifest.created_at {
+        println!(
+            "Created at {} by version {}",
+            created_at,
+            manifest.tool_version.as_deref().unwrap_or("unknown")
+        );
+    }
+}
+
+fn print_metadata<R: Read>(input: &ArchiveReader<R>) -> Result<(), Error> {
+    if let Some(metadata) = &input.metadata {
+        println!("Metadata: {}", serde_json::to_string_pretty(metadata)?);
+    }
     Ok(())
 }
 
@@ -133,6 +438,10 @@ struct Opts {
     password_file: Option<PathBuf>,
     #[clap(short = 'p', long = "password", global = true)]
     password: Option<String>,
+    #[clap(short = 'j', long = "json", global = true)]
+    json: bool,
+    #[clap(long = "verbose", global = true)]
+    verbose: bool,
     #[clap(subcommand)]
     subcommand: Subcommands,
 }
@@ -142,17 +451,62 @@ enum Subcommands {
     #[clap()]
     Encrypt {
         #[clap(short = 'o', long = "output")]
-        output: String,
+        output: Option<String>,
+        #[clap(long = "dry-run")]
+        dry_run: bool,
+        #[clap(long = "hmac")]
+        hmac: bool,
         #[clap(short = 'c', long = "comp", default_value = "3")]
         compression_level: i32,
         #[clap(short = 'v', long = "volume", parse(try_from_str = utils::parse_size))]
         volume_size: Option<u64>,
+        #[clap(short = 't', long = "threads", default_value = "0")]
+        threads: usize,
         #[clap(required = true)]
         input: Vec<String>,
     },
     Decrypt {
+        #[clap(short = 'o', long = "output", default_value = ".")]
+        output: String,
+        #[clap(required = true)]
+        input: String,
+    },
+    EncryptTo {
         #[clap(short = 'o', long = "output")]
-        output: Option<String>,
+        output: String,
+        #[clap(
+            short = 'r',
+            long = "recipient-key",
+            number_of_values = 1,
+            required = true
+        )]
+        recipient_keys: Vec<PathBuf>,
+        #[clap(short = 'c', long = "comp", default_value = "3")]
+        compression_level: i32,
+        #[clap(short = 'v', long = "volume", parse(try_from_str = utils::parse_size))]
+        volume_size: Option<u64>,
+        #[clap(short = 't', long = "threads", default_value = "0")]
+        threads: usize,
+        #[clap(required = true)]
+        input: Vec<String>,
+    },
+    DecryptFrom {
+        #[clap(short = 'o', long = "output", default_value = ".")]
+        output: String,
+        #[clap(short = 'k', long = "key")]
+        key: PathBuf,
+        #[clap(required = true)]
+        input: String,
+    },
+    Reencrypt {
+        #[clap(short = 'o', long = "output")]
+        output: String,
+        #[clap(long = "new-password")]
+        new_password: Option<String>,
+        #[clap(long = "new-passfile")]
+        new_password_file: Option<PathBuf>,
+        #[clap(short = 'v', long = "volume", parse(try_from_str = utils::parse_size))]
+        volume_size: Option<u64>,
         #[clap(required = true)]
         input: String,
     },
@@ -160,40 +514,92 @@ enum Subcommands {
         #[clap(required = true)]
         input: String,
     },
+    Verify {
+        #[clap(long = "hmac")]
+        hmac: bool,
+        #[clap(required = true)]
+        input: String,
+    },
+    List {
+        #[clap(required = true)]
+        input: String,
+    },
 }
 
 fn main() {
     let opts: Opts = Opts::parse();
-    println!("{:?}", opts);
     sodium::init().unwrap();
-    let password = match opts.password {
-        Some(password) => password,
-        None => read_file_content(opts.password_file.unwrap())
-            .unwrap()
-            .trim()
-            .to_owned(),
+    let confirm = matches!(opts.subcommand, Subcommands::Encrypt { .. });
+    let password = match opts.subcommand {
+        Subcommands::EncryptTo { .. } => String::new(),
+        _ => match get_password(opts.password, opts.password_file, confirm) {
+            Ok(password) => password,
+            Err(err) => {
+                eprintln!("Error: {}", ErrorChain(err.as_ref()));
+                std::process::exit(1);
+            }
+        },
     };
     let result: Result<(), Error> = match opts.subcommand {
         Subcommands::Encrypt {
             compression_level,
             volume_size,
             output,
+            dry_run,
+            hmac,
+            threads,
             input,
         } => encrypt_file(
             &input,
-            &output,
+            output.as_deref(),
             &password,
             Some(compression_level),
             volume_size,
+            dry_run,
+            hmac,
+            threads,
+            opts.verbose,
         ),
-        Subcommands::Decrypt { output, input } => {
-            decrypt_file(&input, &output.unwrap_or(".".to_owned()), &password)
+        Subcommands::Decrypt { output, input } => decrypt_file(&input, &output, &password),
+        Subcommands::EncryptTo {
+            output,
+            recipient_keys,
+            compression_level,
+            volume_size,
+            threads,
+            input,
+        } => encrypt_to(
+            &input,
+            &output,
+            &recipient_keys,
+            Some(compression_level),
+            volume_size,
+            threads,
+            opts.verbose,
+        ),
+        Subcommands::DecryptFrom { output, key, input } => {
+            decrypt_from(&input, &output, &key, &password)
         }
-        Subcommands::Test { input } => test_file(&input, &password),
+        Subcommands::Reencrypt {
+            output,
+            new_password,
+            new_password_file,
+            volume_size,
+            input,
+        } => reencrypt_file(
+            &input,
+            &output,
+            &password,
+            new_password,
+            new_password_file,
+            volume_size,
+        ),
+        Subcommands::Test { input } => test_file(&input, &password, opts.json),
+        Subcommands::Verify { hmac, input } => verify_file(&input, &password, hmac),
+        Subcommands::List { input } => list_file(&input, &password, opts.json),
     };
     if let Err(err) = result {
-        println!("Error: {}", err);
-        println!("{}", err.backtrace());
+        eprintln!("Error: {}", ErrorChain(err.as_ref()));
         std::process::exit(1);
     }
 }
@@ -1,9 +1,28 @@
 use std::cmp::min;
 
 use std::borrow::Borrow;
+use std::fmt;
 use std::ops::{Bound, Deref, DerefMut, Index, IndexMut, RangeBounds};
 use std::slice::SliceIndex;
 
+#[derive(Debug)]
+pub struct BufferFullError {
+    pub needed: usize,
+    pub available: usize,
+}
+
+impl fmt::Display for BufferFullError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Buffer full: {} bytes needed, {} bytes available",
+            self.needed, self.available
+        )
+    }
+}
+
+impl std::error::Error for BufferFullError {}
+
 pub struct Buffer {
     buf: Vec<u8>,
     offset: usize,
@@ -105,9 +124,8 @@ impl Buffer {
     pub fn put(&mut self, buf: &[u8]) {
         let mut capacity = self.buf.len() - (self.offset + self.len);
         if buf.len() > capacity {
-            self.buf.copy_within(self.offset..self.offset + self.len, 0);
             capacity += self.offset;
-            self.offset = 0;
+            self.compact();
         }
         if buf.len() > capacity {
             self.buf.reserve(buf.len() - capacity);
@@ -117,6 +135,36 @@ impl Buffer {
         self.len += buf.len();
     }
 
+    pub fn try_put(&mut self, buf: &[u8]) -> Result<(), BufferFullError> {
+        let available = self.remaining_capacity() + self.offset;
+        if buf.len() > available {
+            return Err(BufferFullError {
+                needed: buf.len(),
+                available,
+            });
+        }
+        self.put(buf);
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.offset = 0;
+        self.len = 0;
+    }
+
+    pub fn remaining_capacity(&self) -> usize {
+        self.buf.len() - self.offset - self.len
+    }
+
+    pub fn compact(&mut self) {
+        self.buf.copy_within(self.offset..self.offset + self.len, 0);
+        self.offset = 0;
+    }
+
+    pub fn wasted_capacity(&self) -> usize {
+        self.offset
+    }
+
     pub fn drain_into(&mut self, buf: &mut [u8]) -> usize {
         let size = min(buf.len(), self.len);
         buf[0..size].copy_from_slice(&self.buf[self.offset..self.offset + size]);
@@ -125,7 +173,62 @@ impl Buffer {
         size
     }
 
+    pub fn drain(&mut self, n: usize) -> Vec<u8> {
+        let size = min(n, self.len);
+        let result = self.buf[self.offset..self.offset + size].to_vec();
+        self.offset += size;
+        self.len -= size;
+        result
+    }
+
+    pub fn drain_all(&mut self) -> Vec<u8> {
+        self.drain(self.len)
+    }
+
     pub fn to_vec(&self) -> Vec<u8> {
         self.buf[self.offset..self.offset + self.len].to_vec()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer::Buffer;
+
+    #[test]
+    fn compact_test() {
+        let mut buf = Buffer::with_capacity(16);
+        buf.put(b"0123456789");
+        assert_eq!(buf.wasted_capacity(), 0);
+        buf.drain(4);
+        assert_eq!(buf.wasted_capacity(), 4);
+        assert_eq!(buf.remaining_capacity(), 6);
+        buf.compact();
+        assert_eq!(buf.wasted_capacity(), 0);
+        assert_eq!(buf.remaining_capacity(), 10);
+        assert_eq!(&buf[..], b"456789");
+    }
+    #[test]
+    fn drain_test() {
+        let mut buf = Buffer::with_capacity(16);
+        buf.put(b"0123456789");
+        assert_eq!(buf.drain(4), b"0123");
+        assert_eq!(buf.drain(100), b"456789");
+        assert!(buf.is_empty());
+        assert!(buf.drain(1).is_empty());
+        assert!(buf.drain_all().is_empty());
+    }
+    #[test]
+    fn try_put_test() {
+        let mut buf = Buffer::with_capacity(8);
+        buf.try_put(b"01234567").unwrap();
+        assert_eq!(buf.remaining_capacity(), 0);
+        let err = buf.try_put(b"8").unwrap_err();
+        assert_eq!((err.needed, err.available), (1, 0));
+        buf.drain(3);
+        buf.try_put(b"89a").unwrap();
+        assert_eq!(buf.wasted_capacity(), 0);
+        assert_eq!(buf.remaining_capacity(), 0);
+        assert_eq!(&buf[..], b"3456789a");
+        assert!(buf.try_put(b"b").is_err());
+    }
+}
@@ -0,0 +1,10 @@
+use crate::sodium;
+use anyhow::Error;
+
+pub fn to_hex(data: &[u8]) -> String {
+    sodium::to_hex(data)
+}
+
+pub fn from_hex(s: &str) -> Result<Vec<u8>, Error> {
+    sodium::from_hex(s)
+}
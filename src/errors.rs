@@ -1,67 +1,129 @@
+use std::error::Error as StdError;
 use std::fmt;
+use std::io;
 
-macro_rules! wrap_error {
-    ($message:expr,$err:expr) => {
-        return Error::wrap($message, Box::new($err));
-    };
+#[derive(Debug)]
+pub enum ErrorKind {
+    Io(io::Error),
+    Crypto,
+    Format,
+    InvalidArgument,
+    NotFound,
 }
 
+/// Library functions return `anyhow::Error`; this type travels inside it as a cause and is found with
+/// `kind_of`. It is written by hand rather than derived with thiserror because `source` comes from
+/// `ErrorKind::Io` for I/O errors and from `src` otherwise, which a single `#[source]` field cannot express.
 #[derive(Debug)]
 pub struct Error {
-    src: Option<Box<dyn std::error::Error + 'static + Send + Sync>>,
+    kind: ErrorKind,
+    src: Option<Box<dyn StdError + 'static + Send + Sync>>,
     message: String,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 impl Error {
-    pub fn new(message: &str) -> Self {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
         Self {
+            kind,
             src: None,
-            message: message.to_string(),
+            message: message.into(),
         }
     }
 
-    pub fn wrap(message: &str, err: Box<dyn std::error::Error + Send + Sync>) -> Self {
+    pub fn wrap(
+        kind: ErrorKind,
+        message: impl Into<String>,
+        err: Box<dyn StdError + Send + Sync>,
+    ) -> Self {
         Self {
+            kind,
             src: Some(err),
-            message: message.to_owned(),
+            message: message.into(),
         }
     }
+
+    pub fn crypto(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Crypto, message)
+    }
+
+    pub fn format(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Format, message)
+    }
+
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::InvalidArgument, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::NotFound, message)
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    pub fn chain(&self) -> impl Iterator<Item = &(dyn StdError + 'static)> {
+        chain(self)
+    }
+
+    pub fn fmt_chain(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_chain(self, f)
+    }
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match &self.src {
-            Some(source) => write!(f, "{}: {}", self.message, source),
-            None => write!(f, "{}", self.message),
+pub fn chain<'a>(
+    err: &'a (dyn StdError + 'static),
+) -> impl Iterator<Item = &'a (dyn StdError + 'static)> {
+    std::iter::successors(Some(err), |&err| err.source())
+}
+
+/// Writes an error followed by each of its causes on a separate, further indented line
+pub fn fmt_chain(err: &(dyn StdError + 'static), f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for (depth, cause) in chain(err).enumerate() {
+        if depth == 0 {
+            write!(f, "{}", cause)?;
+        } else {
+            write!(
+                f,
+                "\n{:indent$}Caused by: {}",
+                "",
+                cause,
+                indent = depth * 2
+            )?;
         }
     }
+    Ok(())
 }
 
-impl From<std::io::Error> for Error {
-    fn from(err: std::io::Error) -> Self {
-        Self {
-            src: Some(Box::new(err)),
-            message: "IO error".to_string(),
-        }
+pub struct ErrorChain<'a>(pub &'a (dyn StdError + 'static));
+
+impl fmt::Display for ErrorChain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_chain(self.0, f)
     }
 }
 
-impl From<&str> for Error {
-    fn from(err: &str) -> Self {
-        Self {
-            src: None,
-            message: err.to_string(),
-        }
+/// Finds the kind of the first `Error` in the chain of an `anyhow::Error`
+pub fn kind_of(err: &anyhow::Error) -> Option<&ErrorKind> {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<Error>())
+        .map(Error::kind)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
     }
 }
 
-impl From<String> for Error {
-    fn from(err: String) -> Self {
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
         Self {
+            kind: ErrorKind::Io(err),
             src: None,
-            message: err,
+            message: "IO error".to_string(),
         }
     }
 }
@@ -69,23 +131,43 @@ impl From<String> for Error {
 impl From<serde_json::Error> for Error {
     fn from(err: serde_json::Error) -> Self {
         Self {
+            kind: ErrorKind::Format,
             src: Some(Box::new(err)),
             message: "Error decoding JSON".into(),
         }
     }
 }
 
-impl Into<std::io::Error> for Error {
-    fn into(self) -> std::io::Error {
-        std::io::Error::new(std::io::ErrorKind::Other, self)
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        io::Error::new(io::ErrorKind::Other, err)
     }
 }
 
-impl std::error::Error for Error {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        match &self.src {
-            Some(ref source) => Some(&**source),
-            None => None,
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match (&self.kind, &self.src) {
+            (ErrorKind::Io(err), _) => Some(err),
+            (_, Some(source)) => Some(&**source),
+            (_, None) => None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::{Error, ErrorChain, ErrorKind};
+    use std::io;
+
+    #[test]
+    fn chain_test() {
+        let inner = Error::from(io::Error::new(io::ErrorKind::Other, "disk on fire"));
+        let err = Error::wrap(ErrorKind::Format, "Error reading archive", Box::new(inner));
+        assert_eq!(err.chain().count(), 3);
+        assert_eq!(err.to_string(), "Error reading archive");
+        assert_eq!(
+            ErrorChain(&err).to_string(),
+            "Error reading archive\n  Caused by: IO error\n    Caused by: disk on fire"
+        );
+    }
+}
@@ -1,15 +1,26 @@
+use crate::encoding;
 use crate::kyber;
 use crate::sodium;
 use crate::sodium::crypto_box;
 use crate::sodium::crypto_box::Keypair;
-use crate::sodium::pwhash::pwhash;
+use crate::sodium::hashing::Hasher;
+use crate::sodium::pwhash::{pwhash, PwhashStrength};
 use crate::sodium::randombytes;
+use crate::utils;
 use crate::utils::codecs;
-use failure::{Fail, ResultExt};
+use anyhow::Context;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::Path;
+use zeroize::Zeroize;
+
+const KEY_MAGIC: &[u8; 4] = b"SKEY";
+#[cfg(not(test))]
+const DEFAULT_STRENGTH: PwhashStrength = PwhashStrength::Sensitive;
+// Sensitive limits take seconds and a gigabyte of memory per key file, too much for tests.
+#[cfg(test)]
+const DEFAULT_STRENGTH: PwhashStrength = PwhashStrength::Interactive;
 
 #[derive(Serialize, Deserialize)]
 pub struct Key {
@@ -18,38 +29,88 @@ pub struct Key {
 }
 
 impl Key {
-    pub fn load_from_file<P: AsRef<Path>>(path: P, password: &str) -> Result<Self, failure::Error> {
+    pub fn load_from_file<P: AsRef<Path>>(path: P, password: &str) -> Result<Self, anyhow::Error> {
         let mut file = File::open(path.as_ref()).context("Error opening key file")?;
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)
+            .context("Error reading key file header")?;
         let mut salt = vec![0u8; sodium::pwhash::SALT_BYTES];
-        file.read_exact(&mut salt);
+        let (opslimit, memlimit) = if &magic == KEY_MAGIC {
+            let mut strength = [0u8; 1];
+            file.read_exact(&mut strength)
+                .context("Error reading key file header")?;
+            file.read_exact(&mut salt)
+                .context("Error reading salt from key file")?;
+            PwhashStrength::from_u8(strength[0])?.limits()
+        } else {
+            salt[..magic.len()].copy_from_slice(&magic);
+            file.read_exact(&mut salt[magic.len()..])
+                .context("Error reading salt from key file")?;
+            (3, 1024 * 1024 * 1024)
+        };
         let key = sodium::pwhash::pwhash(
             password,
             sodium::secretbox::KEY_BYTES,
             &salt,
-            3,
-            1024 * 1024 * 1024,
+            opslimit,
+            memlimit,
         )
         .context("Error deriving key from password")?;
         let mut nonce = vec![0u8; sodium::secretbox::NONCE_BYTES];
-        file.read_exact(&mut nonce);
+        file.read_exact(&mut nonce)
+            .context("Error reading nonce from key file")?;
         let mut content = Vec::new();
-        file.read_to_end(&mut content);
-        let content =
+        file.read_to_end(&mut content)
+            .context("Error reading key file content")?;
+        let mut content =
             sodium::secretbox::open(&content, &nonce, &key).context("Error decrypting key")?;
-        println!("{}", String::from_utf8_lossy(&content));
-        let key: Key = serde_json::from_slice(&content).context("Error parsing key")?;
-        return Ok(key);
+        let key = serde_json::from_slice(&content).context("Error parsing key");
+        content.zeroize();
+        return Ok(key?);
+    }
+
+    pub fn change_password<P: AsRef<Path>>(
+        path: P,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<(), anyhow::Error> {
+        let key = Self::load_from_file(path.as_ref(), old_password)?;
+        key.save_to_file(path.as_ref(), new_password)
     }
 
-    pub fn generate() -> Result<Self, failure::Error> {
-        let box_keypair = sodium::crypto_box::Keypair::generate();
-        let kyber_keypair = kyber::Keypair::generate();
+    pub fn generate() -> Result<Self, anyhow::Error> {
+        let box_keypair = sodium::crypto_box::Keypair::generate()?;
+        let kyber_keypair = kyber::Keypair::generate()?;
         Ok(Self {
             box_keypair,
             kyber_keypair,
         })
     }
 
+    pub fn box_keypair(&self) -> &Keypair {
+        &self.box_keypair
+    }
+
+    pub fn load_public_from_file<P: AsRef<Path>>(
+        path: P,
+        password: Option<&str>,
+    ) -> Result<PublicKey, anyhow::Error> {
+        let mut content = Vec::new();
+        File::open(path.as_ref())
+            .and_then(|mut file| file.read_to_end(&mut content))
+            .context("Error reading key file")?;
+        if let Ok(public_key) = serde_json::from_slice(&content) {
+            return Ok(public_key);
+        }
+        let password =
+            password.ok_or_else(|| anyhow::anyhow!("Password required for private key file"))?;
+        Ok(Self::load_from_file(path, password)?.export_public_keys())
+    }
+
+    pub fn fingerprint(&self) -> String {
+        self.export_public_keys().fingerprint()
+    }
+
     pub fn export_public_keys(&self) -> PublicKey {
         PublicKey {
             box_pk: self.box_keypair.pk.clone(),
@@ -61,29 +122,46 @@ impl Key {
         &self,
         path: P,
         password: &str,
-    ) -> Result<(), failure::Error> {
-        let mut file = File::create(path.as_ref()).context("Error creating key file")?;
+    ) -> Result<(), anyhow::Error> {
+        self.save_to_file_with_strength(path, password, DEFAULT_STRENGTH)
+    }
+
+    pub fn save_to_file_with_strength<P: AsRef<Path>>(
+        &self,
+        path: P,
+        password: &str,
+        strength: PwhashStrength,
+    ) -> Result<(), anyhow::Error> {
         let salt = randombytes(sodium::pwhash::SALT_BYTES);
-        file.write_all(&salt)
-            .context("Error writing salt to key file")?;
         let nonce = randombytes(sodium::secretbox::NONCE_BYTES);
-        file.write_all(&nonce)
-            .context("Error writing nonce to key file")?;
+        let (opslimit, memlimit) = strength.limits();
         let key = pwhash(
             password,
             sodium::secretbox::KEY_BYTES,
             &salt,
-            3,
-            1024 * 1024 * 1024,
+            opslimit,
+            memlimit,
         )
         .context("Error deriving key from password")?;
-        let content = sodium::secretbox::seal(
-            &serde_json::to_vec(self).context("Error serializing key")?,
-            &nonce,
-            &key,
-        );
-        file.write_all(&content);
-        Ok(())
+        let mut serialized = serde_json::to_vec(self).context("Error serializing key")?;
+        let content = sodium::secretbox::seal(&serialized, &nonce, &key);
+        serialized.zeroize();
+        let content = content.context("Error encrypting key")?;
+        // Write to a temporary file first so that a failure never destroys an existing key file.
+        utils::atomic_write(path, |file| {
+            file.write_all(KEY_MAGIC)
+                .context("Error writing key file header")?;
+            file.write_all(&[strength.to_u8()])
+                .context("Error writing key file header")?;
+            file.write_all(&salt)
+                .context("Error writing salt to key file")?;
+            file.write_all(&nonce)
+                .context("Error writing nonce to key file")?;
+            file.write_all(&content)
+                .context("Error writing key file content")?;
+            Ok(())
+        })
+        .context("Error saving key file")
     }
 }
 
@@ -102,25 +180,103 @@ pub struct PublicKey {
 }
 
 impl PublicKey {
-    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), failure::Error> {
-        let mut file = File::create(path.as_ref()).context("Error opening file for write")?;
-        file.write_all(&serde_json::to_vec_pretty(self).context("Error serializing public key")?)
-            .context("Error writing public key")?;
-        Ok(())
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, anyhow::Error> {
+        let file = File::open(path.as_ref()).context("Error opening public key file")?;
+        Ok(serde_json::from_reader(file).context("Error parsing public key")?)
+    }
+
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Hasher::new();
+        hasher.update(&self.box_pk);
+        hasher.update(&self.kyber_pk);
+        let hash = encoding::to_hex(&hasher.finalize()[..20]);
+        let groups: Vec<&str> = (0..hash.len())
+            .step_by(4)
+            .map(|start| &hash[start..start + 4])
+            .collect();
+        groups.join(":")
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), anyhow::Error> {
+        let content = serde_json::to_vec_pretty(self).context("Error serializing public key")?;
+        utils::atomic_write(path, |file| {
+            file.write_all(&content)
+                .context("Error writing public key")?;
+            Ok(())
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::key::Key;
+    use crate::key::{Key, PublicKey};
     use crate::sodium;
+    use crate::sodium::pwhash::PwhashStrength;
+    use crate::utils;
+    use std::fs;
 
     #[test]
     fn keygen_test() {
         sodium::init().unwrap();
+        let dir = utils::test_dir("keygen_test");
         let keypair = Key::generate().unwrap();
-        keypair.save_to_file("/tmp/test.key", "password");
-        keypair.export_public_keys().save_to_file("/tmp/test.pub");
-        Key::load_from_file("/tmp/test.key", "password");
+        keypair
+            .save_to_file(dir.join("test.key"), "password")
+            .unwrap();
+        keypair
+            .export_public_keys()
+            .save_to_file(dir.join("test.pub"))
+            .unwrap();
+        Key::load_from_file(dir.join("test.key"), "password").unwrap();
+        PublicKey::load_from_file(dir.join("test.pub")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn change_password_test() {
+        sodium::init().unwrap();
+        let dir = utils::test_dir("change_password_test");
+        let path = dir.join("test.key");
+        let key = Key::generate().unwrap();
+        key.save_to_file(&path, "old").unwrap();
+        Key::change_password(&path, "old", "new").unwrap();
+        assert!(Key::load_from_file(&path, "old").is_err());
+        let loaded = Key::load_from_file(&path, "new").unwrap();
+        assert_eq!(loaded.box_keypair().sk, key.box_keypair().sk);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn strength_test() {
+        sodium::init().unwrap();
+        let dir = utils::test_dir("strength_test");
+        let path = dir.join("test.key");
+        let key = Key::generate().unwrap();
+        key.save_to_file_with_strength(&path, "password", PwhashStrength::Interactive)
+            .unwrap();
+        let loaded = Key::load_from_file(&path, "password").unwrap();
+        assert_eq!(loaded.box_keypair().sk, key.box_keypair().sk);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fingerprint_test() {
+        sodium::init().unwrap();
+        let public_key = PublicKey {
+            box_pk: Vec::new(),
+            kyber_pk: Vec::new(),
+        };
+        assert_eq!(
+            public_key.fingerprint(),
+            "0e57:51c0:26e5:43b2:e8ab:2eb0:6099:daa1:d1e5:df47"
+        );
+        let public_key = PublicKey {
+            box_pk: vec![0u8; 32],
+            kyber_pk: vec![1u8; 1568],
+        };
+        assert_eq!(
+            public_key.fingerprint(),
+            "c494:cc38:36b2:7c09:fa30:693f:492b:a3dd:7cde:7a50"
+        );
     }
 }
@@ -1,7 +1,11 @@
 mod ffi;
 use crate::utils::codecs;
-use failure::ensure;
+use anyhow::{ensure, Error};
 use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+pub const PUBLIC_KEY_BYTES: usize = ffi::pqcrystals_kyber1024_ref_PUBLICKEYBYTES as usize;
+pub const SECRET_KEY_BYTES: usize = ffi::pqcrystals_kyber1024_ref_SECRETKEYBYTES as usize;
 
 #[derive(Serialize, Deserialize)]
 pub struct Keypair {
@@ -24,56 +28,135 @@ pub struct EncapsulationResult {
 }
 
 impl Keypair {
-    pub fn generate() -> Self {
+    pub fn generate() -> Result<Self, Error> {
         let mut keypair = Keypair {
-            pk: vec![0u8; ffi::pqcrystals_kyber1024_ref_PUBLICKEYBYTES as usize],
-            sk: vec![0u8; ffi::pqcrystals_kyber1024_ref_SECRETKEYBYTES as usize],
+            pk: vec![0u8; PUBLIC_KEY_BYTES],
+            sk: vec![0u8; SECRET_KEY_BYTES],
         };
-        unsafe {
-            assert_eq!(
+        ensure!(
+            unsafe {
                 ffi::pqcrystals_kyber1024_ref_keypair(
                     keypair.pk.as_mut_ptr(),
-                    keypair.sk.as_mut_ptr()
-                ),
-                0
-            );
+                    keypair.sk.as_mut_ptr(),
+                )
+            } == 0,
+            "Error generating keypair"
+        );
+        Ok(keypair)
+    }
+
+    pub fn from_bytes(pk: Vec<u8>, sk: Vec<u8>) -> Result<Self, Error> {
+        ensure!(
+            pk.len() == PUBLIC_KEY_BYTES,
+            "Invalid public key length: expected {} bytes, got {}",
+            PUBLIC_KEY_BYTES,
+            pk.len()
+        );
+        ensure!(
+            sk.is_empty() || sk.len() == SECRET_KEY_BYTES,
+            "Invalid secret key length: expected {} bytes, got {}",
+            SECRET_KEY_BYTES,
+            sk.len()
+        );
+        Ok(Keypair { pk, sk })
+    }
+
+    pub fn from_public_bytes(pk: Vec<u8>) -> Result<Self, Error> {
+        Self::from_bytes(pk, vec![])
+    }
+
+    pub fn public_only(&self) -> Keypair {
+        Keypair {
+            pk: self.pk.clone(),
+            sk: vec![],
         }
-        keypair
+    }
+
+    pub fn has_secret_key(&self) -> bool {
+        !self.sk.is_empty()
+    }
+
+    pub fn encapsulate(&self) -> Result<EncapsulationResult, Error> {
+        encapsulate(&self.pk)
+    }
+
+    pub fn decapsulate(&self, ct: &[u8]) -> Result<Vec<u8>, Error> {
+        ensure!(self.has_secret_key(), "Keypair has no secret key");
+        decapsulate(ct, &self.sk)
+    }
+}
+
+impl Drop for Keypair {
+    fn drop(&mut self) {
+        self.sk.zeroize();
     }
 }
 
-pub fn encapsulate(pk: &[u8]) -> EncapsulationResult {
+pub fn encapsulate(pk: &[u8]) -> Result<EncapsulationResult, Error> {
+    ensure!(pk.len() == PUBLIC_KEY_BYTES, "Invalid public key length");
     let mut ss = vec![0u8; ffi::pqcrystals_kyber1024_ref_BYTES as usize];
     let mut ct = vec![0u8; ffi::pqcrystals_kyber1024_ref_CIPHERTEXTBYTES as usize];
-    unsafe {
-        ffi::pqcrystals_kyber1024_ref_enc(ct.as_mut_ptr(), ss.as_mut_ptr(), pk.as_ptr());
-    }
-    EncapsulationResult { ss, ct }
+    ensure!(
+        unsafe { ffi::pqcrystals_kyber1024_ref_enc(ct.as_mut_ptr(), ss.as_mut_ptr(), pk.as_ptr()) }
+            == 0,
+        "Error encapsulating key"
+    );
+    Ok(EncapsulationResult { ss, ct })
 }
 
-pub fn decapsulate(ct: &[u8], sk: &[u8]) -> Vec<u8> {
+pub fn decapsulate(ct: &[u8], sk: &[u8]) -> Result<Vec<u8>, Error> {
+    ensure!(sk.len() == SECRET_KEY_BYTES, "Invalid secret key length");
+    ensure!(
+        ct.len() == ffi::pqcrystals_kyber1024_ref_CIPHERTEXTBYTES as usize,
+        "Invalid ciphertext length"
+    );
     let mut ss = vec![0u8; ffi::pqcrystals_kyber1024_ref_BYTES as usize];
-    unsafe {
-        ffi::pqcrystals_kyber1024_ref_dec(ss.as_mut_ptr(), ct.as_ptr(), sk.as_ptr());
-    }
-    ss
+    ensure!(
+        unsafe { ffi::pqcrystals_kyber1024_ref_dec(ss.as_mut_ptr(), ct.as_ptr(), sk.as_ptr()) }
+            == 0,
+        "Error decapsulating key"
+    );
+    Ok(ss)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::kyber::{decapsulate, encapsulate, Keypair};
-    use crate::sodium::to_hex;
+    use crate::kyber::{decapsulate, encapsulate, Keypair, PUBLIC_KEY_BYTES, SECRET_KEY_BYTES};
 
     #[test]
     fn keygen_test() {
-        let keypair = Keypair::generate();
-        println!("PK={}", base64::encode(&keypair.pk));
-        println!("SK={}", base64::encode(&keypair.sk));
+        let keypair = Keypair::generate().unwrap();
+        assert_eq!(keypair.pk.len(), PUBLIC_KEY_BYTES);
+        assert_eq!(keypair.sk.len(), SECRET_KEY_BYTES);
+        let mut secrets = Vec::new();
         for _ in 1..10 {
-            let ret = encapsulate(&keypair.pk);
-            let ss = decapsulate(&ret.ct, &keypair.sk);
+            let ret = encapsulate(&keypair.pk).unwrap();
+            let ss = decapsulate(&ret.ct, &keypair.sk).unwrap();
             assert_eq!(ss, ret.ss);
-            println!("SS={}", to_hex(&ss));
+            assert!(!secrets.contains(&ss));
+            secrets.push(ss);
         }
     }
+
+    #[test]
+    fn public_only_test() {
+        let keypair = Keypair::generate().unwrap();
+        let public = keypair.public_only();
+        assert!(keypair.has_secret_key());
+        assert!(!public.has_secret_key());
+        let ret = public.encapsulate().unwrap();
+        assert!(public.decapsulate(&ret.ct).is_err());
+        assert_eq!(keypair.decapsulate(&ret.ct).unwrap(), ret.ss);
+    }
+
+    #[test]
+    fn from_bytes_test() {
+        let keypair = Keypair::generate().unwrap();
+        let restored = Keypair::from_bytes(keypair.pk.clone(), keypair.sk.clone()).unwrap();
+        assert!(restored.has_secret_key());
+        let public = Keypair::from_public_bytes(keypair.pk.clone()).unwrap();
+        assert!(!public.has_secret_key());
+        assert!(Keypair::from_bytes(keypair.pk.clone(), vec![0u8; 16]).is_err());
+        assert!(Keypair::from_public_bytes(vec![0u8; PUBLIC_KEY_BYTES - 1]).is_err());
+    }
 }
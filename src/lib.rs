@@ -1,5 +1,7 @@
 pub mod archive;
 pub mod buffer;
+pub mod encoding;
+pub mod errors;
 pub mod key;
 pub mod kyber;
 pub mod parsing;
@@ -7,8 +9,11 @@ pub mod sodium;
 pub mod utils;
 pub mod zstd;
 
+extern crate anyhow;
 extern crate byteorder;
-extern crate failure;
+#[cfg(unix)]
+extern crate libc;
 extern crate regex;
 extern crate serde;
 extern crate serde_json;
+extern crate zeroize;
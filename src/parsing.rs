@@ -1,15 +1,59 @@
-use failure::{err_msg, Error};
-use std::collections::HashMap;
+use anyhow::{anyhow, Error};
+use std::collections::{HashMap, HashSet};
+use std::env;
 
 #[derive(Debug)]
 pub struct Arguments {
     pub flags: HashMap<String, Option<String>>,
+    pub multi_flags: HashMap<String, Vec<String>>,
     pub positionals: Vec<String>,
 }
 
+impl Arguments {
+    pub fn get_required(&self, flag: &str) -> Result<&str, Error> {
+        self.get_optional(flag)
+            .ok_or_else(|| anyhow!("Flag --{} is required", flag))
+    }
+
+    pub fn get_optional(&self, flag: &str) -> Option<&str> {
+        self.flags
+            .get(flag)
+            .and_then(|value| value.as_ref())
+            .map(|value| value.as_str())
+    }
+
+    pub fn get_positional(&self, index: usize, name: &str) -> Result<&str, Error> {
+        self.positionals
+            .get(index)
+            .map(|value| value.as_str())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Expected positional argument <{}> at position {}",
+                    name,
+                    index
+                )
+            })
+    }
+
+    pub fn get_all_positionals(&self, min: usize, name: &str) -> Result<&[String], Error> {
+        if self.positionals.len() < min {
+            return Err(anyhow!(
+                "Expected at least {} positional argument(s) <{}>, got {}",
+                min,
+                name,
+                self.positionals.len()
+            ));
+        }
+        Ok(&self.positionals)
+    }
+}
+
 pub struct Parser {
     pub short_flags: HashMap<String, String>,
     pub long_flags: HashMap<String, u32>,
+    pub env_vars: HashMap<String, String>,
+    pub required_flags: Vec<String>,
+    pub multi_valued: HashSet<String>,
 }
 
 impl Parser {
@@ -17,6 +61,9 @@ impl Parser {
         Self {
             short_flags: HashMap::new(),
             long_flags: HashMap::new(),
+            env_vars: HashMap::new(),
+            required_flags: Vec::new(),
+            multi_valued: HashSet::new(),
         }
     }
 
@@ -33,8 +80,35 @@ impl Parser {
         self.long_flags.insert(long_form.into(), count);
     }
 
+    pub fn add_required_argument(&mut self, long_form: &str, short_form: Option<&str>, count: u32) {
+        self.add_argument(long_form, short_form, count);
+        self.required_flags.push(long_form.into());
+    }
+
+    pub fn add_multi_argument(&mut self, long_form: &str, short_form: Option<&str>) {
+        self.add_argument(long_form, short_form, 1);
+        self.multi_valued.insert(long_form.into());
+    }
+
+    pub fn add_required_multi_argument(&mut self, long_form: &str, short_form: Option<&str>) {
+        self.add_multi_argument(long_form, short_form);
+        self.required_flags.push(long_form.into());
+    }
+
+    pub fn add_argument_with_env(
+        &mut self,
+        long_form: &str,
+        short_form: Option<&str>,
+        count: u32,
+        env_var: &str,
+    ) {
+        self.add_argument(long_form, short_form, count);
+        self.env_vars.insert(long_form.into(), env_var.into());
+    }
+
     pub fn parse_args(&self, args: &[String]) -> Result<Arguments, Error> {
         let mut flags: HashMap<String, Option<String>> = HashMap::new();
+        let mut multi_flags: HashMap<String, Vec<String>> = HashMap::new();
         let mut index = 0usize;
         let mut positionals: Vec<String> = Vec::new();
         while index < args.len() {
@@ -44,7 +118,7 @@ impl Parser {
                 flag_name = Some(
                     self.short_flags
                         .get(&arg[1..])
-                        .ok_or_else(|| err_msg("Invalid short flag"))?
+                        .ok_or_else(|| anyhow!("Invalid short flag"))?
                         .to_string(),
                 );
             } else if arg.starts_with("--") {
@@ -54,16 +128,17 @@ impl Parser {
                 let count = *self
                     .long_flags
                     .get(&flag_name)
-                    .ok_or_else(|| err_msg("Invalid long flag"))?;
+                    .ok_or_else(|| anyhow!("Invalid long flag"))?;
                 if count > 0 {
-                    flags.insert(
-                        flag_name.clone(),
-                        Some(
-                            args.get(index + 1)
-                                .ok_or_else(|| err_msg("No value provided for flag"))?
-                                .clone(),
-                        ),
-                    );
+                    let value = args
+                        .get(index + 1)
+                        .ok_or_else(|| anyhow!("No value provided for flag"))?
+                        .clone();
+                    if self.multi_valued.contains(&flag_name) {
+                        multi_flags.entry(flag_name).or_default().push(value);
+                    } else {
+                        flags.insert(flag_name.clone(), Some(value));
+                    }
                     index += 2;
                 } else {
                     flags.insert(flag_name.clone(), None);
@@ -74,6 +149,142 @@ impl Parser {
                 index += 1;
             }
         }
-        Ok(Arguments { flags, positionals })
+        for (flag_name, env_var) in &self.env_vars {
+            if flags.contains_key(flag_name) || multi_flags.contains_key(flag_name) {
+                continue;
+            }
+            if let Ok(value) = env::var(env_var) {
+                if self.long_flags[flag_name] == 0 && !parse_bool(env_var, &value)? {
+                    continue;
+                }
+                eprintln!(
+                    "Warning: using --{} from environment variable {}",
+                    flag_name, env_var
+                );
+                if self.multi_valued.contains(flag_name) {
+                    multi_flags.insert(flag_name.clone(), vec![value]);
+                    continue;
+                }
+                let value = match self.long_flags[flag_name] {
+                    0 => None,
+                    _ => Some(value),
+                };
+                flags.insert(flag_name.clone(), value);
+            }
+        }
+        let missing: Vec<String> = self
+            .required_flags
+            .iter()
+            .filter(|flag_name| {
+                !flags.contains_key(*flag_name) && !multi_flags.contains_key(*flag_name)
+            })
+            .map(|flag_name| format!("--{}", flag_name))
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow!("Missing required flags: {}", missing.join(", ")));
+        }
+        Ok(Arguments {
+            flags,
+            multi_flags,
+            positionals,
+        })
+    }
+}
+
+fn parse_bool(env_var: &str, value: &str) -> Result<bool, Error> {
+    match value.to_lowercase().as_str() {
+        "1" | "true" | "yes" => Ok(true),
+        "" | "0" | "false" | "no" => Ok(false),
+        _ => Err(anyhow!(
+            "Invalid boolean value {:?} in environment variable {}",
+            value,
+            env_var
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parsing::Parser;
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn required_test() {
+        let mut parser = Parser::new();
+        parser.add_required_argument("input", Some("i"), 1);
+        parser.add_required_argument("output", Some("o"), 1);
+        parser.add_argument("encrypt", Some("e"), 0);
+        let args = parser
+            .parse_args(&to_args(&["-i", "in", "-o", "out"]))
+            .unwrap();
+        assert_eq!(args.flags["input"].as_deref(), Some("in"));
+        let err = parser.parse_args(&to_args(&["-e"])).unwrap_err();
+        assert_eq!(err.to_string(), "Missing required flags: --input, --output");
+    }
+
+    #[test]
+    fn get_test() {
+        let mut parser = Parser::new();
+        parser.add_argument("input", Some("i"), 1);
+        parser.add_argument("encrypt", Some("e"), 0);
+        let args = parser.parse_args(&to_args(&["-i", "in", "-e"])).unwrap();
+        assert_eq!(args.get_required("input").unwrap(), "in");
+        assert_eq!(args.get_optional("encrypt"), None);
+        let args = parser.parse_args(&[]).unwrap();
+        assert_eq!(
+            args.get_required("input").unwrap_err().to_string(),
+            "Flag --input is required"
+        );
+    }
+
+    #[test]
+    fn positional_test() {
+        let parser = Parser::new();
+        let args = parser.parse_args(&to_args(&["a", "b"])).unwrap();
+        assert_eq!(args.get_positional(1, "file").unwrap(), "b");
+        assert_eq!(
+            args.get_positional(2, "file").unwrap_err().to_string(),
+            "Expected positional argument <file> at position 2"
+        );
+        assert_eq!(args.get_all_positionals(2, "file").unwrap(), ["a", "b"]);
+        assert!(args.get_all_positionals(3, "file").is_err());
+    }
+
+    #[test]
+    fn multi_test() {
+        let mut parser = Parser::new();
+        parser.add_required_multi_argument("output", Some("o"));
+        let args = parser
+            .parse_args(&to_args(&["-o", "out1", "--output", "out2", "-o", "out3"]))
+            .unwrap();
+        assert_eq!(args.multi_flags["output"], vec!["out1", "out2", "out3"]);
+        assert!(parser.parse_args(&[]).is_err());
+    }
+
+    #[test]
+    fn env_test() {
+        let mut parser = Parser::new();
+        parser.add_argument_with_env("password", Some("p"), 1, "SECRETS_PARSING_TEST_PASSWORD");
+        std::env::set_var("SECRETS_PARSING_TEST_PASSWORD", "from_env");
+        let args = parser.parse_args(&[]).unwrap();
+        assert_eq!(args.flags["password"].as_deref(), Some("from_env"));
+        let args = parser.parse_args(&to_args(&["-p", "from_args"])).unwrap();
+        assert_eq!(args.flags["password"].as_deref(), Some("from_args"));
+    }
+
+    #[test]
+    fn env_bool_test() {
+        let mut parser = Parser::new();
+        parser.add_argument_with_env("verbose", Some("v"), 0, "SECRETS_PARSING_TEST_VERBOSE");
+        for (value, expected) in &[("1", true), ("Yes", true), ("0", false), ("false", false)] {
+            std::env::set_var("SECRETS_PARSING_TEST_VERBOSE", value);
+            let args = parser.parse_args(&[]).unwrap();
+            assert_eq!(args.flags.contains_key("verbose"), *expected);
+        }
+        std::env::set_var("SECRETS_PARSING_TEST_VERBOSE", "maybe");
+        assert!(parser.parse_args(&[]).is_err());
     }
 }
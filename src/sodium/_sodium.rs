@@ -8,11 +8,15 @@ pub const crypto_aead_xchacha20poly1305_ietf_KEYBYTES: u32 = 32;
 pub const crypto_aead_xchacha20poly1305_ietf_NSECBYTES: u32 = 0;
 pub const crypto_aead_xchacha20poly1305_ietf_NPUBBYTES: u32 = 24;
 pub const crypto_aead_xchacha20poly1305_ietf_ABYTES: u32 = 16;
+pub const crypto_auth_hmacsha256_BYTES: u32 = 32;
+pub const crypto_auth_hmacsha256_KEYBYTES: u32 = 32;
 pub const crypto_box_PUBLICKEYBYTES: u32 = 32;
 pub const crypto_box_SECRETKEYBYTES: u32 = 32;
 pub const crypto_box_NONCEBYTES: u32 = 24;
 pub const crypto_box_MACBYTES: u32 = 16;
+pub const crypto_generichash_BYTES_MAX: u32 = 64;
 pub const crypto_generichash_BYTES: u32 = 32;
+pub const crypto_generichash_KEYBYTES_MAX: u32 = 64;
 pub const crypto_generichash_KEYBYTES: u32 = 32;
 pub const crypto_kdf_blake2b_BYTES_MIN: u32 = 16;
 pub const crypto_kdf_blake2b_BYTES_MAX: u32 = 64;
@@ -100,6 +104,7 @@ pub const crypto_secretstream_xchacha20poly1305_TAG_FINAL: u32 = 3;
 pub const crypto_sign_BYTES: u32 = 64;
 pub const crypto_sign_PUBLICKEYBYTES: u32 = 32;
 pub const crypto_sign_SECRETKEYBYTES: u32 = 64;
+pub const crypto_sign_SEEDBYTES: u32 = 32;
 pub const crypto_secretbox_xchacha20poly1305_KEYBYTES: u32 = 32;
 pub const crypto_secretbox_xchacha20poly1305_NONCEBYTES: u32 = 24;
 pub const crypto_secretbox_xchacha20poly1305_MACBYTES: u32 = 16;
@@ -334,6 +339,47 @@ extern "C" {
 extern "C" {
     pub fn crypto_aead_xchacha20poly1305_ietf_keygen(k: *mut ::std::os::raw::c_uchar);
 }
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct crypto_hash_sha256_state {
+    pub state: [u32; 8usize],
+    pub count: u64,
+    pub buf: [u8; 64usize],
+}
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct crypto_auth_hmacsha256_state {
+    pub ictx: crypto_hash_sha256_state,
+    pub octx: crypto_hash_sha256_state,
+}
+extern "C" {
+    pub fn crypto_auth_hmacsha256(
+        out: *mut ::std::os::raw::c_uchar,
+        in_: *const ::std::os::raw::c_uchar,
+        inlen: ::std::os::raw::c_ulonglong,
+        k: *const ::std::os::raw::c_uchar,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn crypto_auth_hmacsha256_init(
+        state: *mut crypto_auth_hmacsha256_state,
+        key: *const ::std::os::raw::c_uchar,
+        keylen: usize,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn crypto_auth_hmacsha256_update(
+        state: *mut crypto_auth_hmacsha256_state,
+        in_: *const ::std::os::raw::c_uchar,
+        inlen: ::std::os::raw::c_ulonglong,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn crypto_auth_hmacsha256_final(
+        state: *mut crypto_auth_hmacsha256_state,
+        out: *mut ::std::os::raw::c_uchar,
+    ) -> ::std::os::raw::c_int;
+}
 extern "C" {
     pub fn crypto_box_keypair(
         pk: *mut ::std::os::raw::c_uchar,
@@ -482,6 +528,22 @@ extern "C" {
         alg: ::std::os::raw::c_int,
     ) -> ::std::os::raw::c_int;
 }
+extern "C" {
+    pub fn crypto_pwhash_str(
+        out: *mut ::std::os::raw::c_char,
+        passwd: *const ::std::os::raw::c_char,
+        passwdlen: ::std::os::raw::c_ulonglong,
+        opslimit: ::std::os::raw::c_ulonglong,
+        memlimit: usize,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn crypto_pwhash_str_verify(
+        str_: *const ::std::os::raw::c_char,
+        passwd: *const ::std::os::raw::c_char,
+        passwdlen: ::std::os::raw::c_ulonglong,
+    ) -> ::std::os::raw::c_int;
+}
 extern "C" {
     pub fn crypto_secretbox_xsalsa20poly1305_keybytes() -> usize;
 }
@@ -678,6 +740,13 @@ extern "C" {
         sk: *mut ::std::os::raw::c_uchar,
     ) -> ::std::os::raw::c_int;
 }
+extern "C" {
+    pub fn crypto_sign_seed_keypair(
+        pk: *mut ::std::os::raw::c_uchar,
+        sk: *mut ::std::os::raw::c_uchar,
+        seed: *const ::std::os::raw::c_uchar,
+    ) -> ::std::os::raw::c_int;
+}
 extern "C" {
     pub fn crypto_sign(
         sm: *mut ::std::os::raw::c_uchar,
@@ -713,6 +782,18 @@ extern "C" {
         pk: *const ::std::os::raw::c_uchar,
     ) -> ::std::os::raw::c_int;
 }
+extern "C" {
+    pub fn crypto_sign_ed25519_pk_to_curve25519(
+        curve25519_pk: *mut ::std::os::raw::c_uchar,
+        ed25519_pk: *const ::std::os::raw::c_uchar,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn crypto_sign_ed25519_sk_to_curve25519(
+        curve25519_sk: *mut ::std::os::raw::c_uchar,
+        ed25519_sk: *const ::std::os::raw::c_uchar,
+    ) -> ::std::os::raw::c_int;
+}
 extern "C" {
     pub fn randombytes_buf(buf: *mut ::std::os::raw::c_void, size: usize);
 }
@@ -740,6 +821,24 @@ extern "C" {
         bin_len: usize,
     ) -> *mut ::std::os::raw::c_char;
 }
+extern "C" {
+    pub fn sodium_memcmp(
+        b1_: *const ::std::os::raw::c_void,
+        b2_: *const ::std::os::raw::c_void,
+        len: usize,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn sodium_hex2bin(
+        bin: *mut ::std::os::raw::c_uchar,
+        bin_maxlen: usize,
+        hex: *const ::std::os::raw::c_char,
+        hex_len: usize,
+        ignore: *const ::std::os::raw::c_char,
+        bin_len: *mut usize,
+        hex_end: *mut *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
 extern "C" {
     pub fn crypto_secretbox_xchacha20poly1305_keybytes() -> usize;
 }
@@ -1,5 +1,7 @@
+use crate::errors;
 use crate::sodium::_sodium;
-use failure::err_msg;
+use crate::sodium::randombytes;
+use anyhow::ensure;
 use std::ptr::null;
 use std::ptr::null_mut;
 
@@ -7,7 +9,21 @@ pub const ADDITIONAL_BYTES: usize = _sodium::crypto_aead_xchacha20poly1305_ietf_
 pub const NONCE_BYTES: usize = _sodium::crypto_aead_xchacha20poly1305_ietf_NPUBBYTES as usize;
 pub const KEY_BYTES: usize = _sodium::crypto_aead_xchacha20poly1305_ietf_KEYBYTES as usize;
 
-pub fn encrypt(data: &[u8], key: &[u8], nonce: &[u8], ad: Option<&[u8]>) -> Vec<u8> {
+pub fn encrypt(
+    data: &[u8],
+    key: &[u8],
+    nonce: &[u8],
+    ad: Option<&[u8]>,
+) -> Result<Vec<u8>, anyhow::Error> {
+    super::init()?;
+    ensure!(
+        key.len() == KEY_BYTES,
+        errors::Error::invalid_argument("Invalid key length")
+    );
+    ensure!(
+        nonce.len() == NONCE_BYTES,
+        errors::Error::invalid_argument("Invalid nonce length")
+    );
     unsafe {
         let mut ciphertext = vec![0u8; data.len() + ADDITIONAL_BYTES];
         let (ad, ad_len) = match ad {
@@ -15,19 +31,22 @@ pub fn encrypt(data: &[u8], key: &[u8], nonce: &[u8], ad: Option<&[u8]>) -> Vec<
             None => (null(), 0),
         };
         let mut size: u64 = ciphertext.len() as u64;
-        _sodium::crypto_aead_xchacha20poly1305_ietf_encrypt(
-            ciphertext.as_mut_ptr(),
-            &mut size as *mut u64,
-            data.as_ptr(),
-            data.len() as u64,
-            ad,
-            ad_len as u64,
-            null(),
-            nonce.as_ptr(),
-            key.as_ptr(),
+        ensure!(
+            _sodium::crypto_aead_xchacha20poly1305_ietf_encrypt(
+                ciphertext.as_mut_ptr(),
+                &mut size as *mut u64,
+                data.as_ptr(),
+                data.len() as u64,
+                ad,
+                ad_len as u64,
+                null(),
+                nonce.as_ptr(),
+                key.as_ptr(),
+            ) == 0,
+            errors::Error::crypto("Failed to encrypt")
         );
         ciphertext.truncate(size as usize);
-        ciphertext
+        Ok(ciphertext)
     }
 }
 
@@ -36,10 +55,19 @@ pub fn decrypt(
     key: &[u8],
     nonce: &[u8],
     ad: Option<&[u8]>,
-) -> Result<Vec<u8>, failure::Error> {
+) -> Result<Vec<u8>, anyhow::Error> {
+    super::init()?;
+    ensure!(
+        key.len() == KEY_BYTES,
+        errors::Error::invalid_argument("Invalid key length")
+    );
+    ensure!(
+        nonce.len() == NONCE_BYTES,
+        errors::Error::invalid_argument("Invalid nonce length")
+    );
     unsafe {
         if data.len() < ADDITIONAL_BYTES {
-            return Err(err_msg("Ciphertext too short"));
+            return Err(errors::Error::crypto("Ciphertext too short").into());
         }
         let mut plaintext = vec![0u8; data.len() - ADDITIONAL_BYTES];
         let (ad, ad_len) = match ad {
@@ -62,45 +90,82 @@ pub fn decrypt(
                 plaintext.truncate(size as usize);
                 Ok(plaintext)
             }
-            _ => Err(err_msg("Failed to decrypt")),
+            _ => Err(errors::Error::crypto("Failed to decrypt").into()),
         }
     }
 }
 
+pub fn encrypt_prefixed(data: &[u8], key: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut blob = randombytes(NONCE_BYTES);
+    let ciphertext = encrypt(data, key, &blob, None)?;
+    blob.extend_from_slice(&ciphertext);
+    Ok(blob)
+}
+
+pub fn decrypt_prefixed(blob: &[u8], key: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    ensure!(
+        blob.len() >= NONCE_BYTES,
+        errors::Error::crypto("Ciphertext too short")
+    );
+    let (nonce, ciphertext) = blob.split_at(NONCE_BYTES);
+    decrypt(ciphertext, key, nonce, None)
+}
+
 pub mod aes {
+    use crate::errors;
     use crate::sodium::_sodium;
+    use anyhow::ensure;
     use std::ptr::{null, null_mut};
 
     pub const KEY_BYTES: usize = _sodium::crypto_aead_aes256gcm_KEYBYTES as usize;
     pub const ADDITIONAL_BYTES: usize = _sodium::crypto_aead_aes256gcm_ABYTES as usize;
     pub const NONCE_BYTES: usize = _sodium::crypto_aead_aes256gcm_NPUBBYTES as usize;
 
-    pub fn encrypt(data: &[u8], key: &[u8], nonce: &[u8], ad: Option<&[u8]>) -> Vec<u8> {
+    pub fn encrypt(
+        data: &[u8],
+        key: &[u8],
+        nonce: &[u8],
+        ad: Option<&[u8]>,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        crate::sodium::init()?;
+        ensure!(
+            key.len() == KEY_BYTES,
+            errors::Error::invalid_argument("Invalid key length")
+        );
+        ensure!(
+            nonce.len() == NONCE_BYTES,
+            errors::Error::invalid_argument("Invalid nonce length")
+        );
         unsafe {
             let mut ciphertext = vec![0u8; data.len() + ADDITIONAL_BYTES];
             let (ad, ad_len) = match ad {
                 Some(ad) => (ad.as_ptr(), ad.len()),
                 None => (null(), 0),
             };
-            _sodium::crypto_aead_aes256gcm_encrypt(
-                ciphertext.as_mut_ptr(),
-                null_mut(),
-                data.as_ptr(),
-                data.len() as u64,
-                ad,
-                ad_len as u64,
-                null(),
-                nonce.as_ptr(),
-                key.as_ptr(),
+            ensure!(
+                _sodium::crypto_aead_aes256gcm_encrypt(
+                    ciphertext.as_mut_ptr(),
+                    null_mut(),
+                    data.as_ptr(),
+                    data.len() as u64,
+                    ad,
+                    ad_len as u64,
+                    null(),
+                    nonce.as_ptr(),
+                    key.as_ptr(),
+                ) == 0,
+                errors::Error::crypto("Failed to encrypt")
             );
-            ciphertext
+            Ok(ciphertext)
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::sodium::aead::{aes, encrypt, KEY_BYTES, NONCE_BYTES};
+    use crate::sodium::aead::{
+        aes, decrypt_prefixed, encrypt, encrypt_prefixed, KEY_BYTES, NONCE_BYTES,
+    };
     use crate::sodium::{init, randombytes};
     use std::time::Instant;
 
@@ -138,6 +203,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn prefixed_test() {
+        init().unwrap();
+        let key = randombytes(KEY_BYTES);
+        let blob = encrypt_prefixed(b"Hello", &key).unwrap();
+        assert_eq!(decrypt_prefixed(&blob, &key).unwrap(), b"Hello");
+        assert!(decrypt_prefixed(&blob[..NONCE_BYTES - 1], &key).is_err());
+        assert!(encrypt_prefixed(b"Hello", &key[1..]).is_err());
+    }
+
     #[test]
     fn aead_perf_test() {
         init().unwrap();
@@ -0,0 +1,86 @@
+use crate::errors;
+use crate::sodium;
+use crate::sodium::_sodium;
+use anyhow::{ensure, Error};
+
+type HmacState = _sodium::crypto_auth_hmacsha256_state;
+
+pub const KEY_BYTES: usize = _sodium::crypto_auth_hmacsha256_KEYBYTES as usize;
+pub const MAC_BYTES: usize = _sodium::crypto_auth_hmacsha256_BYTES as usize;
+
+pub struct HmacSha256 {
+    state: HmacState,
+}
+
+impl HmacSha256 {
+    pub fn new(key: &[u8]) -> Result<Self, Error> {
+        super::init()?;
+        unsafe {
+            let mut state: HmacState = std::mem::zeroed();
+            ensure!(
+                _sodium::crypto_auth_hmacsha256_init(&mut state, key.as_ptr(), key.len()) == 0,
+                errors::Error::crypto("Error initializing HMAC")
+            );
+            Ok(Self { state })
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) -> Result<(), Error> {
+        unsafe {
+            ensure!(
+                _sodium::crypto_auth_hmacsha256_update(
+                    &mut self.state,
+                    data.as_ptr(),
+                    data.len() as u64,
+                ) == 0,
+                errors::Error::crypto("Error updating HMAC")
+            );
+        }
+        Ok(())
+    }
+
+    pub fn finalize(mut self) -> Result<Vec<u8>, Error> {
+        unsafe {
+            let mut mac = vec![0u8; MAC_BYTES];
+            ensure!(
+                _sodium::crypto_auth_hmacsha256_final(&mut self.state, mac.as_mut_ptr()) == 0,
+                errors::Error::crypto("Error finalizing HMAC")
+            );
+            Ok(mac)
+        }
+    }
+}
+
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut hmac = HmacSha256::new(key)?;
+    hmac.update(data)?;
+    hmac.finalize()
+}
+
+pub fn hmac_sha256_verify(key: &[u8], data: &[u8], mac: &[u8]) -> Result<bool, Error> {
+    Ok(mac.len() == MAC_BYTES && sodium::memcmp(&hmac_sha256(key, data)?, mac))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sodium;
+    use crate::sodium::auth::{hmac_sha256, hmac_sha256_verify, HmacSha256, KEY_BYTES};
+
+    #[test]
+    fn hmac_test() {
+        let mac = hmac_sha256(b"key", b"The quick brown fox jumps over the lazy dog").unwrap();
+        assert_eq!(
+            sodium::to_hex(&mac),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+        let key = sodium::randombytes(KEY_BYTES);
+        let mac = hmac_sha256(&key, b"data").unwrap();
+        let mut hmac = HmacSha256::new(&key).unwrap();
+        hmac.update(b"da").unwrap();
+        hmac.update(b"ta").unwrap();
+        assert_eq!(hmac.finalize().unwrap(), mac);
+        assert!(hmac_sha256_verify(&key, b"data", &mac).unwrap());
+        assert!(!hmac_sha256_verify(&key, b"datb", &mac).unwrap());
+        assert!(!hmac_sha256_verify(&key, b"data", &mac[..16]).unwrap());
+    }
+}
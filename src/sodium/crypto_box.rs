@@ -1,8 +1,10 @@
 use super::_sodium;
+use crate::errors;
 use crate::utils::codecs;
-use failure::ensure;
+use anyhow::ensure;
 use serde::{Deserialize, Serialize};
 use std::os::raw::c_ulonglong;
+use zeroize::Zeroize;
 
 pub const MAC_BYTES: usize = _sodium::crypto_box_MACBYTES as usize;
 
@@ -38,28 +40,74 @@ pub struct Keypair {
 }
 
 impl Keypair {
-    pub fn generate() -> Keypair {
+    pub fn generate() -> Result<Keypair, anyhow::Error> {
+        super::init()?;
         let mut pk = vec![0u8; public_key_bytes()];
         let mut sk = vec![0u8; private_key_bytes()];
-        unsafe {
-            _sodium::crypto_box_keypair(pk.as_mut_ptr(), sk.as_mut_ptr());
-        }
-        Keypair { pk, sk }
+        ensure!(
+            unsafe { _sodium::crypto_box_keypair(pk.as_mut_ptr(), sk.as_mut_ptr()) } == 0,
+            errors::Error::crypto("Error generating keypair")
+        );
+        Ok(Keypair { pk, sk })
+    }
+
+    pub fn from_bytes(pk: Vec<u8>, sk: Vec<u8>) -> Result<Keypair, anyhow::Error> {
+        ensure!(
+            pk.len() == public_key_bytes(),
+            errors::Error::invalid_argument(format!(
+                "Invalid public key length: expected {} bytes, got {}",
+                public_key_bytes(),
+                pk.len()
+            ))
+        );
+        ensure!(
+            sk.is_empty() || sk.len() == private_key_bytes(),
+            errors::Error::invalid_argument(format!(
+                "Invalid private key length: expected {} bytes, got {}",
+                private_key_bytes(),
+                sk.len()
+            ))
+        );
+        Ok(Keypair { pk, sk })
+    }
+
+    pub fn from_public_key(pk: Vec<u8>) -> Result<Keypair, anyhow::Error> {
+        Self::from_bytes(pk, vec![])
+    }
+
+    pub fn has_secret_key(&self) -> bool {
+        !self.sk.is_empty()
+    }
+}
+
+impl Drop for Keypair {
+    fn drop(&mut self) {
+        self.sk.zeroize();
     }
 }
 
-pub fn box_encrypt(data: &[u8], nonce: &[u8], public_key: &[u8], private_key: &[u8]) -> Vec<u8> {
+pub fn box_encrypt(
+    data: &[u8],
+    nonce: &[u8],
+    public_key: &[u8],
+    private_key: &[u8],
+) -> Result<Vec<u8>, anyhow::Error> {
+    super::init()?;
+    check_lengths(nonce, public_key, private_key)?;
     unsafe {
         let mut c = vec![0u8; data.len() + MAC_BYTES];
-        _sodium::crypto_box_easy(
-            c.as_mut_ptr(),
-            data.as_ptr(),
-            data.len() as u64,
-            nonce.as_ptr(),
-            public_key.as_ptr(),
-            private_key.as_ptr(),
+        ensure!(
+            _sodium::crypto_box_easy(
+                c.as_mut_ptr(),
+                data.as_ptr(),
+                data.len() as u64,
+                nonce.as_ptr(),
+                public_key.as_ptr(),
+                private_key.as_ptr(),
+            ) == 0,
+            errors::Error::crypto("Error encrypting box")
         );
-        c
+        Ok(c)
     }
 }
 
@@ -68,11 +116,18 @@ pub fn box_decrypt(
     nonce: &[u8],
     public_key: &[u8],
     private_key: &[u8],
-) -> Result<Vec<u8>, &'static str> {
+) -> Result<Vec<u8>, anyhow::Error> {
+    super::init()?;
+    ensure!(
+        !private_key.is_empty(),
+        errors::Error::invalid_argument("Decryption requires a private key")
+    );
+    check_lengths(nonce, public_key, private_key)?;
     unsafe {
-        if ciphertext.len() < MAC_BYTES {
-            return Err("Ciphertext too short");
-        }
+        ensure!(
+            ciphertext.len() >= MAC_BYTES,
+            errors::Error::crypto("Ciphertext too short")
+        );
         let mut plaintext = vec![0u8; ciphertext.len() - MAC_BYTES];
         match _sodium::crypto_box_open_easy(
             plaintext.as_mut_ptr(),
@@ -83,29 +138,66 @@ pub fn box_decrypt(
             private_key.as_ptr(),
         ) {
             0 => Ok(plaintext),
-            _ => Err("Invalid ciphertext"),
+            _ => Err(errors::Error::crypto("Invalid ciphertext").into()),
         }
     }
 }
 
-pub fn sealed_box_encrypt(m: &[u8], pk: &[u8]) -> Vec<u8> {
+fn check_lengths(nonce: &[u8], public_key: &[u8], private_key: &[u8]) -> Result<(), anyhow::Error> {
+    ensure!(
+        nonce.len() == nonce_bytes(),
+        errors::Error::invalid_argument("Invalid nonce length")
+    );
+    ensure!(
+        public_key.len() == public_key_bytes(),
+        errors::Error::invalid_argument("Invalid public key length")
+    );
+    ensure!(
+        private_key.len() == private_key_bytes(),
+        errors::Error::invalid_argument("Invalid private key length")
+    );
+    Ok(())
+}
+
+pub fn seal_bytes() -> usize {
+    unsafe { _sodium::crypto_box_sealbytes() }
+}
+
+pub fn sealed_box_encrypt(m: &[u8], pk: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    super::init()?;
+    ensure!(
+        pk.len() == public_key_bytes(),
+        errors::Error::invalid_argument("Invalid public key length")
+    );
     unsafe {
         let mut result = vec![0u8; m.len() + _sodium::crypto_box_sealbytes()];
-        _sodium::crypto_box_seal(
-            result.as_mut_ptr(),
-            m.as_ptr(),
-            m.len() as c_ulonglong,
-            pk.as_ptr(),
+        ensure!(
+            _sodium::crypto_box_seal(
+                result.as_mut_ptr(),
+                m.as_ptr(),
+                m.len() as c_ulonglong,
+                pk.as_ptr(),
+            ) == 0,
+            errors::Error::crypto("Error sealing box")
         );
-        result
+        Ok(result)
     }
 }
 
-pub fn sealed_box_decrypt(c: &[u8], pk: &[u8], sk: &[u8]) -> Result<Vec<u8>, failure::Error> {
+pub fn sealed_box_decrypt(c: &[u8], pk: &[u8], sk: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    super::init()?;
+    ensure!(
+        pk.len() == public_key_bytes(),
+        errors::Error::invalid_argument("Invalid public key length")
+    );
+    ensure!(
+        sk.len() == private_key_bytes(),
+        errors::Error::invalid_argument("Invalid private key length")
+    );
     unsafe {
         ensure!(
             c.len() >= _sodium::crypto_box_sealbytes(),
-            "Ciphertext too short"
+            errors::Error::crypto("Ciphertext too short")
         );
         let mut result = vec![0u8; c.len() - _sodium::crypto_box_sealbytes()];
         ensure!(
@@ -116,8 +208,32 @@ pub fn sealed_box_decrypt(c: &[u8], pk: &[u8], sk: &[u8]) -> Result<Vec<u8>, fai
                 pk.as_ptr(),
                 sk.as_ptr(),
             ) == 0,
-            "Invalid ciphertext"
+            errors::Error::crypto("Invalid ciphertext")
         );
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::sodium::crypto_box::{box_decrypt, box_encrypt, nonce_bytes, Keypair};
+    use crate::sodium::randombytes;
+
+    #[test]
+    fn from_bytes_test() {
+        let sender = Keypair::generate().unwrap();
+        let recipient = Keypair::generate().unwrap();
+        let public = Keypair::from_public_key(recipient.pk.clone()).unwrap();
+        assert!(!public.has_secret_key());
+        assert!(Keypair::from_bytes(recipient.pk.clone(), vec![0u8; 8]).is_err());
+        assert!(Keypair::from_public_key(vec![0u8; 8]).is_err());
+        let nonce = randombytes(nonce_bytes());
+        let c = box_encrypt(b"data", &nonce, &public.pk, &sender.sk).unwrap();
+        assert!(box_decrypt(&c, &nonce, &sender.pk, &public.sk).is_err());
+        let restored = Keypair::from_bytes(recipient.pk.clone(), recipient.sk.clone()).unwrap();
+        assert_eq!(
+            box_decrypt(&c, &nonce, &sender.pk, &restored.sk).unwrap(),
+            b"data"
+        );
+    }
+}
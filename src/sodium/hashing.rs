@@ -5,12 +5,29 @@ use std::mem::size_of;
 
 type HashState = _sodium::crypto_generichash_state;
 
+pub const HASH_BYTES: usize = _sodium::crypto_generichash_BYTES as usize;
+pub const HASH_BYTES_MAX: usize = _sodium::crypto_generichash_BYTES_MAX as usize;
+
 pub struct Hasher {
     state: *mut HashState,
+    outlen: usize,
 }
 
 impl Hasher {
     pub fn new() -> Self {
+        Self::new_keyed(&[])
+    }
+
+    pub fn new_blake2b512() -> Self {
+        Self::with_outlen(&[], HASH_BYTES_MAX)
+    }
+
+    pub fn new_keyed(key: &[u8]) -> Self {
+        Self::with_outlen(key, HASH_BYTES)
+    }
+
+    fn with_outlen(key: &[u8], outlen: usize) -> Self {
+        super::init().unwrap();
         unsafe {
             let state = alloc::alloc(
                 alloc::Layout::from_size_align(
@@ -21,11 +38,15 @@ impl Hasher {
             ) as *mut HashState;
             _sodium::crypto_generichash_init(
                 state,
-                std::ptr::null(),
-                0usize,
-                _sodium::crypto_generichash_BYTES as usize,
+                if key.is_empty() {
+                    std::ptr::null()
+                } else {
+                    key.as_ptr()
+                },
+                key.len(),
+                outlen,
             );
-            Self { state }
+            Self { state, outlen }
         }
     }
     pub fn update(&mut self, data: &[u8]) {
@@ -35,12 +56,8 @@ impl Hasher {
     }
     pub fn finalize(&mut self) -> Vec<u8> {
         unsafe {
-            let mut hash = vec![0u8; _sodium::crypto_generichash_BYTES as usize];
-            _sodium::crypto_generichash_final(
-                self.state,
-                hash.as_mut_ptr(),
-                _sodium::crypto_generichash_BYTES as usize,
-            );
+            let mut hash = vec![0u8; self.outlen];
+            _sodium::crypto_generichash_final(self.state, hash.as_mut_ptr(), self.outlen);
             hash
         }
     }
@@ -60,3 +77,24 @@ impl Drop for Hasher {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::sodium::hashing::{Hasher, HASH_BYTES, HASH_BYTES_MAX};
+    use crate::sodium::{init, to_hex};
+
+    #[test]
+    fn blake2b512_test() {
+        init().unwrap();
+        assert_eq!(Hasher::new().finalize().len(), HASH_BYTES);
+        let mut hasher = Hasher::new_blake2b512();
+        hasher.update(b"abc");
+        let hash = hasher.finalize();
+        assert_eq!(hash.len(), HASH_BYTES_MAX);
+        assert_eq!(
+            to_hex(&hash),
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+             7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+        );
+    }
+}
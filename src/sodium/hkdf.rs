@@ -0,0 +1,73 @@
+use crate::errors;
+use crate::sodium::auth::{HmacSha256, MAC_BYTES};
+use anyhow::{ensure, Error};
+
+pub const HASH_BYTES: usize = MAC_BYTES;
+pub const MAX_OUTPUT_BYTES: usize = 255 * HASH_BYTES;
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> Result<Vec<u8>, Error> {
+    let mut hmac = HmacSha256::new(key)?;
+    for part in parts {
+        hmac.update(part)?;
+    }
+    hmac.finalize()
+}
+
+pub fn extract(salt: Option<&[u8]>, ikm: &[u8]) -> Result<Vec<u8>, Error> {
+    match salt {
+        Some(salt) => hmac(salt, &[ikm]),
+        None => hmac(&[0u8; HASH_BYTES], &[ikm]),
+    }
+}
+
+pub fn expand(prk: &[u8], info: &[u8], len: usize) -> Result<Vec<u8>, Error> {
+    ensure!(
+        prk.len() >= HASH_BYTES,
+        errors::Error::invalid_argument(format!("PRK must be at least {} bytes", HASH_BYTES))
+    );
+    ensure!(
+        len <= MAX_OUTPUT_BYTES,
+        errors::Error::invalid_argument(format!(
+            "Output length must be at most {} bytes",
+            MAX_OUTPUT_BYTES
+        ))
+    );
+    let mut okm = Vec::with_capacity(len);
+    let mut block = Vec::new();
+    let mut counter = 1u8;
+    while okm.len() < len {
+        block = hmac(prk, &[&block, info, &[counter]])?;
+        let needed = (len - okm.len()).min(HASH_BYTES);
+        okm.extend_from_slice(&block[..needed]);
+        counter = counter.wrapping_add(1);
+    }
+    Ok(okm)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sodium;
+    use crate::sodium::hkdf::{expand, extract, MAX_OUTPUT_BYTES};
+
+    #[test]
+    fn rfc5869_test() {
+        let ikm = [0x0bu8; 22];
+        let salt = sodium::from_hex("000102030405060708090a0b0c").unwrap();
+        let info = sodium::from_hex("f0f1f2f3f4f5f6f7f8f9").unwrap();
+        let prk = extract(Some(&salt), &ikm).unwrap();
+        assert_eq!(
+            sodium::to_hex(&prk),
+            "077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5"
+        );
+        let okm = expand(&prk, &info, 42).unwrap();
+        assert_eq!(
+            sodium::to_hex(&okm),
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865"
+        );
+        assert!(expand(&prk, &info, MAX_OUTPUT_BYTES + 1).is_err());
+        assert_eq!(
+            extract(None, &ikm).unwrap(),
+            extract(Some(&[0u8; 32]), &ikm).unwrap()
+        );
+    }
+}
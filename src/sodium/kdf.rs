@@ -1,9 +1,36 @@
+use crate::errors;
 use crate::sodium::_sodium;
+use anyhow::{ensure, Error};
 use std::os::raw::c_char;
+use std::str::FromStr;
 
 pub const KEY_BYTES: usize = _sodium::crypto_kdf_KEYBYTES as usize;
+pub const BYTES_MIN: usize = _sodium::crypto_kdf_BYTES_MIN as usize;
+pub const BYTES_MAX: usize = _sodium::crypto_kdf_BYTES_MAX as usize;
+pub const CONTEXT_BYTES: usize = _sodium::crypto_kdf_CONTEXTBYTES as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfContext(pub [u8; CONTEXT_BYTES]);
+
+impl FromStr for KdfContext {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        ensure!(
+            s.len() <= CONTEXT_BYTES,
+            errors::Error::invalid_argument(format!(
+                "Context must be at most {} bytes",
+                CONTEXT_BYTES
+            ))
+        );
+        let mut context = [b' '; CONTEXT_BYTES];
+        context[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(KdfContext(context))
+    }
+}
 
 pub fn keygen() -> Vec<u8> {
+    super::init().unwrap();
     unsafe {
         let mut key = vec![0u8; KEY_BYTES];
         _sodium::crypto_kdf_keygen(key.as_mut_ptr());
@@ -11,16 +38,110 @@ pub fn keygen() -> Vec<u8> {
     }
 }
 
-pub fn derive(master_key: &[u8], subkey_len: usize, subkey_id: u64, context: &str) -> Vec<u8> {
+pub fn derive(
+    master_key: &[u8],
+    subkey_len: usize,
+    subkey_id: u64,
+    context: &KdfContext,
+) -> Result<Vec<u8>, Error> {
+    super::init()?;
+    ensure!(
+        master_key.len() == KEY_BYTES,
+        errors::Error::invalid_argument("Invalid master key length")
+    );
     unsafe {
         let mut subkey = vec![0u8; subkey_len];
-        _sodium::crypto_kdf_derive_from_key(
-            subkey.as_mut_ptr(),
-            subkey_len,
-            subkey_id,
-            context.as_ptr() as *const c_char,
-            master_key.as_ptr(),
+        ensure!(
+            _sodium::crypto_kdf_derive_from_key(
+                subkey.as_mut_ptr(),
+                subkey_len,
+                subkey_id,
+                context.0.as_ptr() as *const c_char,
+                master_key.as_ptr(),
+            ) == 0,
+            errors::Error::crypto("Error deriving subkey")
+        );
+        Ok(subkey)
+    }
+}
+
+pub fn derive_checked(
+    master_key: &[u8],
+    subkey_len: usize,
+    subkey_id: u64,
+    context: &KdfContext,
+) -> Result<Vec<u8>, Error> {
+    ensure!(
+        subkey_len >= BYTES_MIN && subkey_len <= BYTES_MAX,
+        errors::Error::invalid_argument(format!(
+            "Subkey length must be between {} and {} bytes",
+            BYTES_MIN, BYTES_MAX
+        ))
+    );
+    derive(master_key, subkey_len, subkey_id, context)
+}
+
+pub fn derive_many(
+    master_key: &[u8],
+    count: usize,
+    subkey_len: usize,
+    context: &KdfContext,
+) -> Result<Vec<Vec<u8>>, Error> {
+    (0..count as u64)
+        .map(|subkey_id| derive(master_key, subkey_len, subkey_id, context))
+        .collect()
+}
+
+pub fn derive_many_checked(
+    master_key: &[u8],
+    count: usize,
+    subkey_len: usize,
+    context: &KdfContext,
+) -> Result<Vec<Vec<u8>>, Error> {
+    (0..count as u64)
+        .map(|subkey_id| derive_checked(master_key, subkey_len, subkey_id, context))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sodium;
+    use crate::sodium::kdf::{
+        derive, derive_checked, derive_many, derive_many_checked, keygen, KdfContext,
+    };
+    use std::str::FromStr;
+
+    #[test]
+    fn derive_many_test() {
+        sodium::init().unwrap();
+        let master_key = keygen();
+        let context = KdfContext(*b"testtest");
+        let subkeys = derive_many(&master_key, 3, 32, &context).unwrap();
+        assert_eq!(subkeys.len(), 3);
+        for (id, subkey) in subkeys.iter().enumerate() {
+            assert_eq!(
+                *subkey,
+                derive(&master_key, 32, id as u64, &context).unwrap()
+            );
+        }
+        assert_ne!(subkeys[0], subkeys[1]);
+        assert_eq!(
+            derive_many_checked(&master_key, 3, 32, &context).unwrap(),
+            subkeys
+        );
+        assert!(derive_checked(&master_key, 8, 0, &context).is_err());
+    }
+
+    #[test]
+    fn context_test() {
+        assert_eq!(
+            KdfContext::from_str("test").unwrap(),
+            KdfContext(*b"test    ")
+        );
+        assert_eq!(
+            KdfContext::from_str("testtest").unwrap(),
+            KdfContext(*b"testtest")
         );
-        subkey
+        assert!(KdfContext::from_str("too long context").is_err());
     }
 }
@@ -1,5 +1,17 @@
+use crate::errors;
+use crate::sodium;
 use crate::sodium::_sodium;
-use failure::ensure;
+use crate::sodium::hashing;
+use crate::sodium::hashing::Hasher;
+use crate::sodium::kdf;
+use crate::sodium::kdf::KdfContext;
+use crate::sodium::secretstream::SecretStream;
+use anyhow::{ensure, Error};
+
+const CONFIRMATION_CONTEXT: KdfContext = KdfContext(*b"kx_confm");
+
+pub const PUBLIC_KEY_BYTES: usize = _sodium::crypto_kx_PUBLICKEYBYTES as usize;
+pub const SECRET_KEY_BYTES: usize = _sodium::crypto_kx_SECRETKEYBYTES as usize;
 
 pub struct Keypair {
     pub pk: Vec<u8>,
@@ -12,18 +24,36 @@ pub struct SessionKeys {
 }
 
 impl Keypair {
-    pub fn generate() -> Self {
+    pub fn generate() -> Result<Self, Error> {
+        sodium::init()?;
         unsafe {
             let mut keypair = Self {
-                pk: vec![0u8; _sodium::crypto_kx_publickeybytes()],
-                sk: vec![0u8; _sodium::crypto_kx_secretkeybytes()],
+                pk: vec![0u8; PUBLIC_KEY_BYTES],
+                sk: vec![0u8; SECRET_KEY_BYTES],
             };
-            _sodium::crypto_kx_keypair(keypair.pk.as_mut_ptr(), keypair.sk.as_mut_ptr());
-            keypair
+            ensure!(
+                _sodium::crypto_kx_keypair(keypair.pk.as_mut_ptr(), keypair.sk.as_mut_ptr()) == 0,
+                errors::Error::crypto("Error generating key exchange keypair")
+            );
+            Ok(keypair)
         }
     }
 
-    pub fn server_session_keys(&self, client_pk: &[u8]) -> Result<SessionKeys, failure::Error> {
+    fn check_lengths(&self, peer_pk: &[u8], peer: &str) -> Result<(), Error> {
+        ensure!(
+            self.pk.len() == PUBLIC_KEY_BYTES && self.sk.len() == SECRET_KEY_BYTES,
+            errors::Error::invalid_argument("Invalid key exchange keypair")
+        );
+        ensure!(
+            peer_pk.len() == PUBLIC_KEY_BYTES,
+            errors::Error::invalid_argument(format!("Invalid {} public key length", peer))
+        );
+        Ok(())
+    }
+
+    pub fn server_session_keys(&self, client_pk: &[u8]) -> Result<SessionKeys, anyhow::Error> {
+        sodium::init()?;
+        self.check_lengths(client_pk, "client")?;
         unsafe {
             let mut key = SessionKeys {
                 rx: vec![0u8; _sodium::crypto_kx_sessionkeybytes()],
@@ -37,13 +67,15 @@ impl Keypair {
                     self.sk.as_ptr(),
                     client_pk.as_ptr(),
                 ) == 0,
-                "Invalid client public key"
+                errors::Error::invalid_argument("Invalid client public key")
             );
             Ok(key)
         }
     }
 
-    pub fn client_session_keys(&self, server_pk: &[u8]) -> Result<SessionKeys, failure::Error> {
+    pub fn client_session_keys(&self, server_pk: &[u8]) -> Result<SessionKeys, anyhow::Error> {
+        sodium::init()?;
+        self.check_lengths(server_pk, "server")?;
         unsafe {
             let mut key = SessionKeys {
                 rx: vec![0u8; _sodium::crypto_kx_sessionkeybytes()],
@@ -57,9 +89,109 @@ impl Keypair {
                     self.sk.as_ptr(),
                     server_pk.as_ptr(),
                 ) == 0,
-                "Invalid client public key"
+                errors::Error::invalid_argument("Invalid server public key")
             );
             Ok(key)
         }
     }
 }
+
+impl SessionKeys {
+    pub fn push_stream(&self) -> Result<SecretStream, Error> {
+        SecretStream::new_push(&self.tx)
+    }
+
+    pub fn pull_stream(&self, header: &[u8]) -> Result<SecretStream, Error> {
+        SecretStream::new_pull(header, &self.rx)
+    }
+}
+
+fn confirmation(key: &[u8], client_hello: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut hasher = Hasher::new_keyed(&kdf::derive(
+        key,
+        hashing::HASH_BYTES,
+        0,
+        &CONFIRMATION_CONTEXT,
+    )?);
+    hasher.update(client_hello);
+    Ok(hasher.finalize())
+}
+
+pub struct Handshake {
+    keypair: Keypair,
+    server_pk: Vec<u8>,
+}
+
+impl Handshake {
+    pub fn initiate(server_pk: &[u8]) -> Result<(Handshake, Vec<u8>), Error> {
+        ensure!(
+            server_pk.len() == PUBLIC_KEY_BYTES,
+            errors::Error::invalid_argument("Invalid server public key length")
+        );
+        let keypair = Keypair::generate()?;
+        let client_hello = keypair.pk.clone();
+        Ok((
+            Handshake {
+                keypair,
+                server_pk: server_pk.to_vec(),
+            },
+            client_hello,
+        ))
+    }
+
+    pub fn respond(
+        client_hello: &[u8],
+        server_keypair: &Keypair,
+    ) -> Result<(SessionKeys, Vec<u8>), Error> {
+        ensure!(
+            client_hello.len() == PUBLIC_KEY_BYTES,
+            errors::Error::invalid_argument("Invalid client hello")
+        );
+        let keys = server_keypair.server_session_keys(client_hello)?;
+        let server_hello = confirmation(&keys.tx, client_hello)?;
+        Ok((keys, server_hello))
+    }
+
+    pub fn finish(self, server_hello: &[u8]) -> Result<SessionKeys, Error> {
+        let keys = self.keypair.client_session_keys(&self.server_pk)?;
+        ensure!(
+            sodium::memcmp(&confirmation(&keys.rx, &self.keypair.pk)?, server_hello),
+            errors::Error::crypto("Server key confirmation failed")
+        );
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sodium;
+    use crate::sodium::kx::{Handshake, Keypair};
+
+    #[test]
+    fn handshake_test() {
+        sodium::init().unwrap();
+        let server_keypair = Keypair::generate().unwrap();
+        let (handshake, client_hello) = Handshake::initiate(&server_keypair.pk).unwrap();
+        let (server_keys, server_hello) =
+            Handshake::respond(&client_hello, &server_keypair).unwrap();
+        let client_keys = handshake.finish(&server_hello).unwrap();
+        let mut pusher = client_keys.push_stream().unwrap();
+        let mut puller = server_keys.pull_stream(&pusher.get_header()).unwrap();
+        let ciphertext = pusher.push(b"hello").unwrap();
+        assert_eq!(puller.pull(&ciphertext).unwrap(), b"hello");
+
+        let (handshake, _) = Handshake::initiate(&server_keypair.pk).unwrap();
+        assert!(handshake.finish(&server_hello).is_err());
+    }
+
+    #[test]
+    fn short_key_test() {
+        sodium::init().unwrap();
+        let keypair = Keypair::generate().unwrap();
+        let short = &keypair.pk[..16];
+        assert!(Handshake::initiate(short).is_err());
+        assert!(Handshake::respond(short, &keypair).is_err());
+        assert!(keypair.client_session_keys(short).is_err());
+        assert!(keypair.server_session_keys(short).is_err());
+    }
+}
@@ -1,30 +1,35 @@
+use crate::errors;
 use once_cell::sync::OnceCell;
 use std::ffi::CStr;
 
 #[allow(dead_code, non_upper_case_globals, non_camel_case_types)]
 mod _sodium;
 pub mod aead;
+pub mod auth;
 pub mod crypto_box;
 pub mod hashing;
+pub mod hkdf;
 pub mod kdf;
 pub mod kx;
 pub mod pwhash;
 pub mod secretbox;
+pub mod secretbox_xchacha;
 pub mod secretstream;
 #[allow(dead_code)]
 pub mod signing;
 
 static INITIALIZED: OnceCell<::std::os::raw::c_int> = OnceCell::new();
 
-pub fn init() -> Result<(), failure::Error> {
+pub fn init() -> Result<(), anyhow::Error> {
     if *INITIALIZED.get_or_init(|| unsafe { _sodium::sodium_init() }) < 0 {
-        Err(failure::err_msg("Failed to initialize libsodium"))
+        Err(errors::Error::crypto("Failed to initialize libsodium").into())
     } else {
         Ok(())
     }
 }
 
 pub fn randombytes(length: usize) -> Vec<u8> {
+    init().unwrap();
     unsafe {
         let mut buf = vec![0u8; length];
         _sodium::randombytes_buf(buf.as_mut_ptr() as *mut std::ffi::c_void, length);
@@ -32,6 +37,27 @@ pub fn randombytes(length: usize) -> Vec<u8> {
     }
 }
 
+pub fn random_u32() -> u32 {
+    init().unwrap();
+    unsafe { _sodium::randombytes_random() }
+}
+
+pub fn random_uniform(upper_bound: u32) -> u32 {
+    init().unwrap();
+    unsafe { _sodium::randombytes_uniform(upper_bound) }
+}
+
+pub fn memcmp(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && unsafe {
+            _sodium::sodium_memcmp(
+                a.as_ptr() as *const std::ffi::c_void,
+                b.as_ptr() as *const std::ffi::c_void,
+                a.len(),
+            ) == 0
+        }
+}
+
 pub fn increment(n: &mut [u8]) {
     unsafe {
         _sodium::sodium_increment(n.as_mut_ptr(), n.len());
@@ -54,9 +80,32 @@ pub fn to_hex(data: &[u8]) -> String {
     }
 }
 
+pub fn from_hex<T: AsRef<[u8]>>(hex: T) -> Result<Vec<u8>, anyhow::Error> {
+    let hex = hex.as_ref();
+    let mut result = vec![0u8; hex.len() / 2];
+    let mut bin_len = 0usize;
+    let mut hex_end = std::ptr::null();
+    unsafe {
+        let ret = _sodium::sodium_hex2bin(
+            result.as_mut_ptr(),
+            result.len(),
+            hex.as_ptr() as *const i8,
+            hex.len(),
+            std::ptr::null(),
+            &mut bin_len,
+            &mut hex_end,
+        );
+        if ret != 0 || hex_end != hex.as_ptr().add(hex.len()) as *const i8 {
+            return Err(errors::Error::format("Invalid hex string").into());
+        }
+    }
+    result.truncate(bin_len);
+    Ok(result)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::sodium::{increment, to_hex};
+    use crate::sodium::{from_hex, increment, init, memcmp, random_uniform, to_hex};
 
     #[test]
     fn to_hex_test() {
@@ -64,6 +113,29 @@ mod tests {
         assert_eq!(to_hex(data), "123456789abcdef012");
     }
 
+    #[test]
+    fn from_hex_test() {
+        let data = b"\x12\x34\x56\x78\x9a\xbc\xde\xf0\x12";
+        assert_eq!(from_hex("123456789abcdef012").unwrap(), data);
+        assert_eq!(from_hex(b"123456789ABCDEF012").unwrap(), data);
+        assert!(from_hex("12345").is_err());
+        assert!(from_hex("12zz").is_err());
+    }
+
+    #[test]
+    fn memcmp_test() {
+        assert!(memcmp(b"abc", b"abc"));
+        assert!(!memcmp(b"abc", b"abd"));
+        assert!(!memcmp(b"abc", b"ab"));
+    }
+
+    #[test]
+    fn random_uniform_test() {
+        init().unwrap();
+        assert!((0..1000).all(|_| random_uniform(10) < 10));
+        assert_eq!(random_uniform(1), 0);
+    }
+
     #[test]
     fn increment_test() {
         let mut data = b"\xff\xff\xff\x00".to_vec();
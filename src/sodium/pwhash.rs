@@ -1,7 +1,110 @@
+use crate::errors;
 use crate::sodium::_sodium;
-use failure::{err_msg, Error};
+use crate::sodium::randombytes;
+use crate::utils::codecs;
+use anyhow::{ensure, Context, Error};
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
 
 pub const SALT_BYTES: usize = _sodium::crypto_pwhash_SALTBYTES as usize;
+pub const STR_BYTES: usize = _sodium::crypto_pwhash_STRBYTES as usize;
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PwhashAlgorithm {
+    Argon2i,
+    Argon2id,
+}
+
+impl PwhashAlgorithm {
+    fn id(self) -> i32 {
+        match self {
+            PwhashAlgorithm::Argon2i => _sodium::crypto_pwhash_ALG_ARGON2I13 as i32,
+            PwhashAlgorithm::Argon2id => _sodium::crypto_pwhash_ALG_ARGON2ID13 as i32,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum PwhashStrength {
+    Interactive,
+    Moderate,
+    Sensitive,
+}
+
+impl PwhashStrength {
+    pub fn limits(self) -> (u64, usize) {
+        match self {
+            PwhashStrength::Interactive => (
+                _sodium::crypto_pwhash_OPSLIMIT_INTERACTIVE as u64,
+                _sodium::crypto_pwhash_MEMLIMIT_INTERACTIVE as usize,
+            ),
+            PwhashStrength::Moderate => (
+                _sodium::crypto_pwhash_OPSLIMIT_MODERATE as u64,
+                _sodium::crypto_pwhash_MEMLIMIT_MODERATE as usize,
+            ),
+            PwhashStrength::Sensitive => (
+                _sodium::crypto_pwhash_OPSLIMIT_SENSITIVE as u64,
+                _sodium::crypto_pwhash_MEMLIMIT_SENSITIVE as usize,
+            ),
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            PwhashStrength::Interactive => 0,
+            PwhashStrength::Moderate => 1,
+            PwhashStrength::Sensitive => 2,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Result<Self, Error> {
+        match value {
+            0 => Ok(PwhashStrength::Interactive),
+            1 => Ok(PwhashStrength::Moderate),
+            2 => Ok(PwhashStrength::Sensitive),
+            _ => Err(errors::Error::invalid_argument("Invalid password hashing strength").into()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct PwhashParams {
+    pub opslimit: u64,
+    pub memlimit: usize,
+    pub algorithm: PwhashAlgorithm,
+    #[serde(
+        serialize_with = "codecs::to_base64",
+        deserialize_with = "codecs::from_base64"
+    )]
+    pub salt: Vec<u8>,
+}
+
+impl PwhashParams {
+    pub fn generate(algorithm: PwhashAlgorithm, opslimit: u64, memlimit: usize) -> Self {
+        Self {
+            opslimit,
+            memlimit,
+            algorithm,
+            salt: randombytes(SALT_BYTES),
+        }
+    }
+
+    pub fn derive_key(&self, password: &str, outlen: usize) -> Result<Vec<u8>, Error> {
+        ensure!(
+            self.salt.len() == SALT_BYTES,
+            errors::Error::invalid_argument("Invalid salt length")
+        );
+        pwhash_with_alg(
+            password,
+            outlen,
+            &self.salt,
+            self.opslimit,
+            self.memlimit,
+            self.algorithm,
+        )
+    }
+}
 
 pub fn pwhash(
     password: &str,
@@ -10,6 +113,25 @@ pub fn pwhash(
     opslimit: u64,
     memlimit: usize,
 ) -> Result<Vec<u8>, Error> {
+    pwhash_with_alg(
+        password,
+        outlen,
+        salt,
+        opslimit,
+        memlimit,
+        PwhashAlgorithm::Argon2id,
+    )
+}
+
+pub fn pwhash_with_alg(
+    password: &str,
+    outlen: usize,
+    salt: &[u8],
+    opslimit: u64,
+    memlimit: usize,
+    alg: PwhashAlgorithm,
+) -> Result<Vec<u8>, Error> {
+    super::init()?;
     let mut out = vec![0u8; outlen];
     unsafe {
         match _sodium::crypto_pwhash(
@@ -20,29 +142,103 @@ pub fn pwhash(
             salt.as_ptr(),
             opslimit,
             memlimit,
-            _sodium::crypto_pwhash_ALG_ARGON2ID13 as i32,
+            alg.id(),
         ) {
             0 => Ok(out),
-            _ => Err(err_msg("Error deriving key from password")),
+            _ => Err(errors::Error::crypto("Error deriving key from password").into()),
+        }
+    }
+}
+
+pub fn pwhash_str(password: &str, opslimit: u64, memlimit: usize) -> Result<String, Error> {
+    super::init()?;
+    let mut out = vec![0u8; STR_BYTES];
+    unsafe {
+        match _sodium::crypto_pwhash_str(
+            out.as_mut_ptr() as *mut i8,
+            password.as_ptr() as *const i8,
+            password.len() as u64,
+            opslimit,
+            memlimit,
+        ) {
+            0 => {
+                let len = out.iter().position(|&c| c == 0).unwrap_or(out.len());
+                out.truncate(len);
+                Ok(String::from_utf8(out).context("Invalid password hash string")?)
+            }
+            _ => Err(errors::Error::crypto("Error hashing password").into()),
         }
     }
 }
 
+pub fn pwhash_str_verify(hash: &str, password: &str) -> bool {
+    if super::init().is_err() {
+        return false;
+    }
+    let hash = match CString::new(hash) {
+        Ok(hash) => hash,
+        Err(_) => return false,
+    };
+    unsafe {
+        _sodium::crypto_pwhash_str_verify(
+            hash.as_ptr(),
+            password.as_ptr() as *const i8,
+            password.len() as u64,
+        ) == 0
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::sodium::pwhash::{pwhash, SALT_BYTES};
+    use crate::sodium::pwhash::{
+        pwhash, pwhash_str, pwhash_str_verify, pwhash_with_alg, PwhashAlgorithm, PwhashParams,
+        SALT_BYTES,
+    };
     use crate::sodium::randombytes;
     use crate::sodium::secretstream::KEY_BYTES;
-    use std::time::Instant;
 
     #[test]
     fn pwhash_test() {
         let salt = randombytes(SALT_BYTES);
-        let start = Instant::now();
-        println!(
-            "{:?}",
-            pwhash("password", KEY_BYTES, &salt, 3, 1024 * 1024 * 1024).unwrap()
+        let key = pwhash("password", KEY_BYTES, &salt, 3, 8 * 1024 * 1024).unwrap();
+        assert_eq!(key.len(), KEY_BYTES);
+        assert_eq!(
+            pwhash("password", KEY_BYTES, &salt, 3, 8 * 1024 * 1024).unwrap(),
+            key
+        );
+        let other_salt = randombytes(SALT_BYTES);
+        assert_ne!(
+            pwhash("password", KEY_BYTES, &other_salt, 3, 8 * 1024 * 1024).unwrap(),
+            key
+        );
+    }
+
+    #[test]
+    fn pwhash_str_test() {
+        let hash = pwhash_str("password", 2, 64 * 1024 * 1024).unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(pwhash_str_verify(&hash, "password"));
+        assert!(!pwhash_str_verify(&hash, "wrong password"));
+    }
+
+    #[test]
+    fn params_test() {
+        let params = PwhashParams::generate(PwhashAlgorithm::Argon2i, 3, 32 * 1024 * 1024);
+        let json = serde_json::to_string(&params).unwrap();
+        assert!(json.contains("\"algorithm\":\"argon2i\""));
+        let decoded: PwhashParams = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, params);
+        assert_eq!(
+            decoded.derive_key("password", KEY_BYTES).unwrap(),
+            pwhash_with_alg(
+                "password",
+                KEY_BYTES,
+                &params.salt,
+                3,
+                32 * 1024 * 1024,
+                PwhashAlgorithm::Argon2i
+            )
+            .unwrap()
         );
-        println!("{}", Instant::now().duration_since(start).as_secs_f64());
     }
 }
@@ -0,0 +1,83 @@
+use crate::errors;
+use crate::sodium::_sodium;
+use anyhow::ensure;
+use std::os::raw::c_ulonglong;
+
+pub const MAC_BYTES: usize = _sodium::crypto_secretbox_xchacha20poly1305_MACBYTES as usize;
+pub const NONCE_BYTES: usize = _sodium::crypto_secretbox_xchacha20poly1305_NONCEBYTES as usize;
+pub const KEY_BYTES: usize = _sodium::crypto_secretbox_xchacha20poly1305_KEYBYTES as usize;
+
+pub fn seal(m: &[u8], nonce: &[u8], key: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    super::init()?;
+    ensure!(
+        nonce.len() == NONCE_BYTES,
+        errors::Error::invalid_argument("Invalid nonce length")
+    );
+    ensure!(
+        key.len() == KEY_BYTES,
+        errors::Error::invalid_argument("Invalid key length")
+    );
+    let mut result = vec![0u8; m.len() + MAC_BYTES];
+    ensure!(
+        unsafe {
+            _sodium::crypto_secretbox_xchacha20poly1305_easy(
+                result.as_mut_ptr(),
+                m.as_ptr(),
+                m.len() as c_ulonglong,
+                nonce.as_ptr(),
+                key.as_ptr(),
+            )
+        } == 0,
+        errors::Error::crypto("Error sealing secretbox")
+    );
+    Ok(result)
+}
+
+pub fn open(c: &[u8], nonce: &[u8], key: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    super::init()?;
+    ensure!(
+        nonce.len() == NONCE_BYTES,
+        errors::Error::invalid_argument("Invalid nonce length")
+    );
+    ensure!(
+        key.len() == KEY_BYTES,
+        errors::Error::invalid_argument("Invalid key length")
+    );
+    ensure!(
+        c.len() >= MAC_BYTES,
+        errors::Error::crypto("Ciphertext too short")
+    );
+    let mut result = vec![0u8; c.len() - MAC_BYTES];
+    ensure!(
+        unsafe {
+            _sodium::crypto_secretbox_xchacha20poly1305_open_easy(
+                result.as_mut_ptr(),
+                c.as_ptr(),
+                c.len() as c_ulonglong,
+                nonce.as_ptr(),
+                key.as_ptr(),
+            )
+        } == 0,
+        errors::Error::crypto("Error opening secretbox")
+    );
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sodium::secretbox_xchacha::{open, seal, KEY_BYTES, NONCE_BYTES};
+    use crate::sodium::{init, randombytes};
+
+    #[test]
+    fn seal_test() {
+        init().unwrap();
+        let key = randombytes(KEY_BYTES);
+        let nonce = randombytes(NONCE_BYTES);
+        let mut c = seal(b"message", &nonce, &key).unwrap();
+        assert_eq!(open(&c, &nonce, &key).unwrap(), b"message");
+        c[0] ^= 1;
+        assert!(open(&c, &nonce, &key).is_err());
+        assert!(seal(b"message", &nonce[1..], &key).is_err());
+        assert!(open(&c, &nonce, &key[1..]).is_err());
+    }
+}
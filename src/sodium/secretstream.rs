@@ -1,16 +1,25 @@
 use super::_sodium;
+use crate::errors;
 use crate::sodium;
 use crate::sodium::randombytes;
-use crate::sodium::to_hex;
+use anyhow::{ensure, Error};
 use byteorder::ByteOrder;
-use failure::{ensure, err_msg, Error};
 use std::ptr::{null, null_mut};
+use zeroize::Zeroize;
 
 pub const ADDITIONAL_BYTES: usize = _sodium::crypto_aead_xchacha20poly1305_ietf_ABYTES as usize;
 pub const KEY_BYTES: usize = _sodium::crypto_aead_xchacha20poly1305_ietf_KEYBYTES as usize;
 pub const HEADER_BYTES: usize = _sodium::crypto_aead_xchacha20poly1305_ietf_NPUBBYTES as usize - 8;
 
-#[derive(PartialEq, Eq)]
+pub const fn additional_bytes_per_message() -> usize {
+    ADDITIONAL_BYTES
+}
+
+pub const fn header_bytes() -> usize {
+    HEADER_BYTES
+}
+
+#[derive(PartialEq, Eq, Clone)]
 pub enum Direction {
     Push,
     Pull,
@@ -21,6 +30,35 @@ pub struct SecretStream {
     key: Vec<u8>,
     counter: u64,
     dir: Direction,
+    ad: Option<Vec<u8>>,
+}
+
+#[derive(Default)]
+pub struct SecretStreamBuilder {
+    ad: Option<Vec<u8>>,
+}
+
+impl SecretStreamBuilder {
+    pub fn new() -> SecretStreamBuilder {
+        SecretStreamBuilder::default()
+    }
+
+    pub fn with_ad(mut self, ad: &[u8]) -> Self {
+        self.ad = Some(Vec::from(ad));
+        self
+    }
+
+    pub fn build_push(self, key: &[u8]) -> Result<SecretStream, Error> {
+        let mut stream = SecretStream::new_push(key)?;
+        stream.ad = self.ad;
+        Ok(stream)
+    }
+
+    pub fn build_pull(self, header: &[u8], key: &[u8]) -> Result<SecretStream, Error> {
+        let mut stream = SecretStream::new_pull(header, key)?;
+        stream.ad = self.ad;
+        Ok(stream)
+    }
 }
 
 pub fn generate_key() -> Vec<u8> {
@@ -37,39 +75,84 @@ impl SecretStream {
         self.header.clone()
     }
 
+    pub fn counter(&self) -> u64 {
+        self.counter
+    }
+
+    /// Returns an independent pull stream positioned at `counter`. Push streams cannot be forked,
+    /// since two pushers sharing a key and counter would reuse nonces.
+    pub fn fork_pull(&self, counter: u64) -> Result<SecretStream, Error> {
+        ensure!(
+            self.dir == Direction::Pull,
+            errors::Error::invalid_argument("Only pull streams can be forked")
+        );
+        Ok(SecretStream {
+            header: self.header.clone(),
+            key: self.key.clone(),
+            counter,
+            dir: Direction::Pull,
+            ad: self.ad.clone(),
+        })
+    }
+
     pub fn new_push(key: &[u8]) -> Result<SecretStream, Error> {
         sodium::init()?;
-        ensure!(key.len() == KEY_BYTES, "Key length should be {}", KEY_BYTES);
+        ensure!(
+            key.len() == KEY_BYTES,
+            errors::Error::invalid_argument(format!("Key length should be {}", KEY_BYTES))
+        );
         let header = randombytes(HEADER_BYTES);
         Ok(SecretStream {
             header,
             key: Vec::from(key),
             counter: 0,
             dir: Direction::Push,
+            ad: None,
         })
     }
 
     pub fn new_pull(header: &[u8], key: &[u8]) -> Result<SecretStream, Error> {
         sodium::init()?;
-        ensure!(header.len() == HEADER_BYTES, "Header too short");
-        ensure!(key.len() == KEY_BYTES, "Key length invalid");
+        ensure!(
+            header.len() == HEADER_BYTES,
+            errors::Error::invalid_argument("Header too short")
+        );
+        ensure!(
+            key.len() == KEY_BYTES,
+            errors::Error::invalid_argument("Key length invalid")
+        );
         Ok(SecretStream {
             header: Vec::from(header),
             key: Vec::from(key),
             counter: 0,
             dir: Direction::Pull,
+            ad: None,
         })
     }
 
-    pub fn push(&mut self, data: &[u8], ad: Option<&[u8]>) -> Result<Vec<u8>, Error> {
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let ad = self.ad.take();
+        let result = self.push_with_ad(data, ad.as_deref());
+        self.ad = ad;
+        result
+    }
+
+    pub fn pull(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        let ad = self.ad.take();
+        let result = self.pull_with_ad(ciphertext, ad.as_deref());
+        self.ad = ad;
+        result
+    }
+
+    pub fn push_with_ad(&mut self, data: &[u8], ad: Option<&[u8]>) -> Result<Vec<u8>, Error> {
         unsafe {
             ensure!(
                 self.dir == Direction::Push,
-                "Stream should be in push direction"
+                errors::Error::invalid_argument("Stream should be in push direction")
             );
             ensure!(
                 data.len() <= _sodium::crypto_aead_xchacha20poly1305_ietf_messagebytes_max(),
-                "Message too long"
+                errors::Error::invalid_argument("Message too long")
             );
             let mut ciphertext = vec![0u8; data.len() + ADDITIONAL_BYTES];
             let (ad, adlen) = match ad {
@@ -80,7 +163,6 @@ impl SecretStream {
             let mut nonce = vec![0u8; _sodium::crypto_aead_xchacha20poly1305_ietf_npubbytes()];
             nonce[0..HEADER_BYTES].copy_from_slice(&self.header);
             byteorder::BigEndian::write_u64(&mut nonce[HEADER_BYTES..], self.counter);
-            println!("{:}", to_hex(&nonce));
             _sodium::crypto_aead_xchacha20poly1305_ietf_encrypt(
                 ciphertext.as_mut_ptr(),
                 &mut clen as *mut u64,
@@ -98,13 +180,16 @@ impl SecretStream {
         }
     }
 
-    pub fn pull(&mut self, ciphertext: &[u8], ad: Option<&[u8]>) -> Result<Vec<u8>, Error> {
+    pub fn pull_with_ad(&mut self, ciphertext: &[u8], ad: Option<&[u8]>) -> Result<Vec<u8>, Error> {
         unsafe {
             ensure!(
                 self.dir == Direction::Pull,
-                "Stream should be in pull direction"
+                errors::Error::invalid_argument("Stream should be in pull direction")
+            );
+            ensure!(
+                ciphertext.len() >= ADDITIONAL_BYTES,
+                errors::Error::crypto("Ciphertext too short")
             );
-            ensure!(ciphertext.len() >= ADDITIONAL_BYTES, "Ciphertext too short");
             let mut plaintext = vec![0u8; ciphertext.len() - ADDITIONAL_BYTES];
             let (ad, adlen) = match ad {
                 Some(ad) => (ad.as_ptr(), ad.len() as u64),
@@ -129,12 +214,18 @@ impl SecretStream {
                     self.counter += 1;
                     Ok(plaintext)
                 }
-                _ => Err(err_msg("Invalid ciphertext")),
+                _ => Err(errors::Error::crypto("Invalid ciphertext").into()),
             }
         }
     }
 }
 
+impl Drop for SecretStream {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::sodium::secretstream;
@@ -149,7 +240,7 @@ mod tests {
         let iterations = 40000;
         let start = Instant::now();
         for i in 1..=iterations {
-            let _ = pusher.push(&input, None);
+            let _ = pusher.push(&input);
         }
         let time = Instant::now().duration_since(start).as_secs_f64();
         println!(
@@ -181,9 +272,38 @@ mod tests {
         let mut puller = secretstream::SecretStream::new_pull(&pusher.get_header(), &key).unwrap();
         let input = randombytes(1024);
         for _ in 1..100 {
-            let c = pusher.push(&input, None).unwrap();
-            let p = puller.pull(&c, None).unwrap();
+            let c = pusher.push(&input).unwrap();
+            let p = puller.pull(&c).unwrap();
             assert_eq!(p, input);
         }
     }
+
+    #[test]
+    fn stream_ad_test() {
+        let key = secretstream::generate_key();
+        let mut pusher = secretstream::SecretStreamBuilder::new()
+            .with_ad(b"session")
+            .build_push(&key)
+            .unwrap();
+        let header = pusher.get_header();
+        let mut puller = secretstream::SecretStreamBuilder::new()
+            .with_ad(b"session")
+            .build_pull(&header, &key)
+            .unwrap();
+        let c = pusher.push(b"hello").unwrap();
+        assert_eq!(puller.pull(&c).unwrap(), b"hello");
+        let c = pusher.push_with_ad(b"world", Some(b"record")).unwrap();
+        assert!(puller
+            .fork_pull(puller.counter())
+            .unwrap()
+            .pull(&c)
+            .is_err());
+        assert_eq!(puller.pull_with_ad(&c, Some(b"record")).unwrap(), b"world");
+        let mut other = secretstream::SecretStream::new_pull(&header, &key)
+            .unwrap()
+            .fork_pull(pusher.counter())
+            .unwrap();
+        assert!(other.pull(&pusher.push(b"x").unwrap()).is_err());
+        assert!(pusher.fork_pull(0).is_err());
+    }
 }
@@ -1,32 +1,111 @@
 use super::_sodium;
-use failure::{err_msg, Error};
+use crate::errors;
+use crate::sodium::crypto_box;
+use crate::sodium::hashing::Hasher;
+use crate::utils::codecs;
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use zeroize::Zeroize;
 
 pub const PUBLIC_KEY_BYTES: usize = _sodium::crypto_sign_PUBLICKEYBYTES as usize;
 pub const SECRET_KEY_BYTES: usize = _sodium::crypto_sign_SECRETKEYBYTES as usize;
 pub const SIG_BYTES: usize = _sodium::crypto_sign_BYTES as usize;
+pub const SEED_BYTES: usize = _sodium::crypto_sign_SEEDBYTES as usize;
 
+#[derive(Serialize, Deserialize)]
 pub struct Keypair {
+    #[serde(
+        serialize_with = "codecs::to_base64",
+        deserialize_with = "codecs::from_base64"
+    )]
     pub public_key: Vec<u8>,
+    #[serde(
+        serialize_with = "codecs::to_base64",
+        deserialize_with = "codecs::from_base64",
+        skip_serializing_if = "Vec::is_empty",
+        default
+    )]
     pub private_key: Vec<u8>,
 }
 
 impl Keypair {
-    pub fn generate() -> Self {
+    pub fn generate() -> Result<Self, Error> {
+        super::init()?;
         let mut pk = vec![0u8; PUBLIC_KEY_BYTES];
         let mut sk = vec![0u8; SECRET_KEY_BYTES];
         unsafe {
             _sodium::crypto_sign_keypair(pk.as_mut_ptr(), sk.as_mut_ptr());
         }
-        Self {
+        Ok(Self {
             public_key: pk,
             private_key: sk,
+        })
+    }
+
+    pub fn from_seed(seed: &[u8]) -> Result<Self, Error> {
+        super::init()?;
+        if seed.len() != SEED_BYTES {
+            return Err(errors::Error::invalid_argument("Incorrect seed length").into());
         }
+        let mut pk = vec![0u8; PUBLIC_KEY_BYTES];
+        let mut sk = vec![0u8; SECRET_KEY_BYTES];
+        unsafe {
+            _sodium::crypto_sign_seed_keypair(pk.as_mut_ptr(), sk.as_mut_ptr(), seed.as_ptr());
+        }
+        Ok(Self {
+            public_key: pk,
+            private_key: sk,
+        })
+    }
+
+    pub fn seed(&self) -> Option<Vec<u8>> {
+        if self.private_key.len() != SECRET_KEY_BYTES {
+            return None;
+        }
+        Some(self.private_key[..SEED_BYTES].to_vec())
+    }
+
+    pub fn to_box_keypair(&self) -> Result<crypto_box::Keypair, Error> {
+        super::init()?;
+        if self.public_key.len() != PUBLIC_KEY_BYTES {
+            return Err(errors::Error::invalid_argument("Incorrect public key length").into());
+        }
+        if self.private_key.len() != SECRET_KEY_BYTES {
+            return Err(errors::Error::invalid_argument("Incorrect secret key length").into());
+        }
+        let mut pk = vec![0u8; crypto_box::public_key_bytes()];
+        let mut sk = vec![0u8; crypto_box::private_key_bytes()];
+        unsafe {
+            if _sodium::crypto_sign_ed25519_pk_to_curve25519(
+                pk.as_mut_ptr(),
+                self.public_key.as_ptr(),
+            ) != 0
+            {
+                return Err(errors::Error::crypto("Error converting public key").into());
+            }
+            if _sodium::crypto_sign_ed25519_sk_to_curve25519(
+                sk.as_mut_ptr(),
+                self.private_key.as_ptr(),
+            ) != 0
+            {
+                return Err(errors::Error::crypto("Error converting secret key").into());
+            }
+        }
+        Ok(crypto_box::Keypair { pk, sk })
+    }
+}
+
+impl Drop for Keypair {
+    fn drop(&mut self) {
+        self.private_key.zeroize();
     }
 }
 
 pub fn sign(data: &[u8], secret_key: &[u8]) -> Result<Vec<u8>, Error> {
+    super::init()?;
     if secret_key.len() != SECRET_KEY_BYTES {
-        return Err(err_msg("Incorrect secret key length"));
+        return Err(errors::Error::invalid_argument("Incorrect secret key length").into());
     }
     let mut sm = vec![0u8; data.len() + SIG_BYTES];
     unsafe {
@@ -42,11 +121,12 @@ pub fn sign(data: &[u8], secret_key: &[u8]) -> Result<Vec<u8>, Error> {
 }
 
 pub fn open(signed_message: &[u8], public_key: &[u8]) -> Result<Vec<u8>, Error> {
+    super::init()?;
     if signed_message.len() < SIG_BYTES {
-        return Err(err_msg("Signed message too short"));
+        return Err(errors::Error::crypto("Signed message too short").into());
     }
     if public_key.len() != PUBLIC_KEY_BYTES {
-        return Err(err_msg("Incorrect public key length"));
+        return Err(errors::Error::invalid_argument("Incorrect public key length").into());
     }
     let mut m = vec![0u8; signed_message.len() - SIG_BYTES];
     unsafe {
@@ -58,15 +138,16 @@ pub fn open(signed_message: &[u8], public_key: &[u8]) -> Result<Vec<u8>, Error>
             public_key.as_ptr(),
         ) {
             0 => Ok(m),
-            _ => Err(err_msg("Signature verification failed")),
+            _ => Err(errors::Error::crypto("Signature verification failed").into()),
         }
     }
 }
 
 pub fn sign_detached(data: &[u8], secret_key: &[u8]) -> Result<Vec<u8>, Error> {
+    super::init()?;
     let mut sig = vec![0u8; SIG_BYTES];
     if secret_key.len() != SECRET_KEY_BYTES {
-        return Err(err_msg("Incorrect secret key length"));
+        return Err(errors::Error::invalid_argument("Incorrect secret key length").into());
     }
     unsafe {
         _sodium::crypto_sign_detached(
@@ -81,11 +162,12 @@ pub fn sign_detached(data: &[u8], secret_key: &[u8]) -> Result<Vec<u8>, Error> {
 }
 
 pub fn verify_detached(data: &[u8], signature: &[u8], public_key: &[u8]) -> Result<bool, Error> {
+    super::init()?;
     if public_key.len() != PUBLIC_KEY_BYTES {
-        return Err(err_msg("Incorrect public key length"));
+        return Err(errors::Error::invalid_argument("Incorrect public key length").into());
     }
     if signature.len() != SIG_BYTES {
-        return Err(err_msg("Incorrect signature length"));
+        return Err(errors::Error::invalid_argument("Incorrect signature length").into());
     }
     unsafe {
         Ok(_sodium::crypto_sign_verify_detached(
@@ -96,3 +178,139 @@ pub fn verify_detached(data: &[u8], signature: &[u8], public_key: &[u8]) -> Resu
         ) == 0)
     }
 }
+
+pub struct StreamSigner {
+    hasher: Hasher,
+    secret_key: Vec<u8>,
+}
+
+impl StreamSigner {
+    pub fn new(secret_key: &[u8]) -> Self {
+        Self {
+            hasher: Hasher::new(),
+            secret_key: secret_key.to_vec(),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    pub fn finalize(mut self) -> Result<Vec<u8>, Error> {
+        sign_detached(&self.hasher.finalize(), &self.secret_key)
+    }
+}
+
+impl Drop for StreamSigner {
+    fn drop(&mut self) {
+        self.secret_key.zeroize();
+    }
+}
+
+pub struct StreamVerifier {
+    hasher: Hasher,
+}
+
+impl StreamVerifier {
+    pub fn new() -> Self {
+        Self {
+            hasher: Hasher::new(),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    pub fn verify(mut self, signature: &[u8], public_key: &[u8]) -> Result<bool, Error> {
+        verify_detached(&self.hasher.finalize(), signature, public_key)
+    }
+}
+
+impl Default for StreamVerifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn sign_stream<R: Read>(reader: &mut R, secret_key: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut signer = StreamSigner::new(secret_key);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let count = reader.read(&mut buf)?;
+        if count == 0 {
+            break;
+        }
+        signer.update(&buf[..count]);
+    }
+    signer.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sodium;
+    use crate::sodium::crypto_box;
+    use crate::sodium::signing::{sign_detached, sign_stream, Keypair, StreamVerifier};
+
+    #[test]
+    fn box_keypair_test() {
+        sodium::init().unwrap();
+        let keypair = Keypair::generate().unwrap().to_box_keypair().unwrap();
+        let c = crypto_box::sealed_box_encrypt(b"message", &keypair.pk).unwrap();
+        assert_eq!(
+            crypto_box::sealed_box_decrypt(&c, &keypair.pk, &keypair.sk).unwrap(),
+            b"message"
+        );
+    }
+
+    #[test]
+    fn seed_test() {
+        sodium::init().unwrap();
+        let keypair = Keypair::generate().unwrap();
+        let restored = Keypair::from_seed(&keypair.seed().unwrap()).unwrap();
+        assert_eq!(restored.public_key, keypair.public_key);
+        assert_eq!(restored.private_key, keypair.private_key);
+        assert!(Keypair::from_seed(&[0u8; 16]).is_err());
+    }
+
+    #[test]
+    fn stream_test() {
+        sodium::init().unwrap();
+        let keypair = Keypair::generate().unwrap();
+        let data = vec![42u8; 200 * 1024];
+        let signature = sign_stream(&mut data.as_slice(), &keypair.private_key).unwrap();
+        let mut verifier = StreamVerifier::new();
+        for chunk in data.chunks(1000) {
+            verifier.update(chunk).unwrap();
+        }
+        assert!(verifier.verify(&signature, &keypair.public_key).unwrap());
+        let mut verifier = StreamVerifier::default();
+        verifier.update(&data[1..]).unwrap();
+        assert!(!verifier.verify(&signature, &keypair.public_key).unwrap());
+        let mut verifier = StreamVerifier::new();
+        verifier.update(&data).unwrap();
+        assert!(!verifier
+            .verify(
+                &sign_detached(&data, &keypair.private_key).unwrap(),
+                &keypair.public_key
+            )
+            .unwrap());
+    }
+
+    #[test]
+    fn serde_test() {
+        let keypair = Keypair::generate().unwrap();
+        let json = serde_json::to_string(&keypair).unwrap();
+        let decoded: Keypair = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.public_key, keypair.public_key);
+        assert_eq!(decoded.private_key, keypair.private_key);
+        let public = Keypair {
+            public_key: keypair.public_key.clone(),
+            private_key: vec![],
+        };
+        let json = serde_json::to_string(&public).unwrap();
+        assert!(!json.contains("private_key"));
+        let decoded: Keypair = serde_json::from_str(&json).unwrap();
+        assert!(decoded.private_key.is_empty());
+    }
+}
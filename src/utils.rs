@@ -1,27 +1,56 @@
 use crate::parsing;
+use crate::sodium;
 use crate::sodium::hashing;
-use failure::{err_msg, Error, ResultExt};
+use anyhow::{anyhow, ensure, Context, Error};
 use regex::Regex;
+use std::env;
+use std::ffi::OsString;
 use std::fs;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::prelude::*;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+pub const PASSWORD_ENV: &str = "SECRETS_PASSWORD";
+pub const PASSFILE_ENV: &str = "SECRETS_PASSFILE";
+
+pub fn read_passfile<P: AsRef<Path>>(path: P) -> Result<String, Error> {
+    let mut password = String::new();
+    File::open(path.as_ref())
+        .and_then(|ref mut file| file.read_to_string(&mut password))
+        .context("Error reading from passfile")?;
+    Ok(password.trim().to_owned())
+}
+
+pub fn get_password_from_env() -> Result<Option<String>, Error> {
+    if let Ok(password) = env::var(PASSWORD_ENV) {
+        eprintln!(
+            "Warning: using password from environment variable {}",
+            PASSWORD_ENV
+        );
+        Ok(Some(password))
+    } else if let Ok(passfile) = env::var(PASSFILE_ENV) {
+        eprintln!(
+            "Warning: using passfile from environment variable {}",
+            PASSFILE_ENV
+        );
+        Ok(Some(read_passfile(passfile)?))
+    } else {
+        Ok(None)
+    }
+}
 
 pub fn get_password(args: &parsing::Arguments) -> Result<String, Error> {
     if args.flags.contains_key("password") && args.flags.contains_key("passfile") {
-        return Err(err_msg("-p/--password and -P/--passfile are in conflict"));
-    }
-    if let Some(password) = args.flags.get("password") {
-        Ok(password.as_ref().unwrap().clone())
-    } else if let Some(passfile) = args.flags.get("passfile") {
-        let mut password = String::new();
-        File::open(passfile.as_ref().unwrap())
-            .and_then(|ref mut file| file.read_to_string(&mut password))
-            .context("Error reading from passfile")?;
-        Ok(password.trim().to_owned())
+        return Err(anyhow!("-p/--password and -P/--passfile are in conflict"));
+    }
+    if let Some(password) = args.get_optional("password") {
+        Ok(password.to_owned())
+    } else if let Some(passfile) = args.get_optional("passfile") {
+        read_passfile(passfile)
     } else {
-        Err(err_msg("Please specify password or passfile"))
+        get_password_from_env()?.ok_or_else(|| anyhow!("Please specify password or passfile"))
     }
 }
 
@@ -29,7 +58,7 @@ pub fn parse_size(size: &str) -> Result<u64, Error> {
     let pattern: Regex = Regex::new("^([0-9.]+)(K|M|G)?$").unwrap();
     let capture = pattern
         .captures(size)
-        .ok_or_else(|| err_msg("Invalid size specification"))?;
+        .ok_or_else(|| anyhow!("Invalid size specification"))?;
     let mut base: f64 = capture[1].parse::<f64>().context("Error parsing number")?;
     base *= match capture.get(2).map(|s| s.as_str()) {
         Some("K") => 1024,
@@ -40,21 +69,178 @@ pub fn parse_size(size: &str) -> Result<u64, Error> {
     Ok(base.floor() as u64)
 }
 
+/// Splits `path` into the components of an object path, dropping prefixes, root and `.` components
+pub fn get_path_components<P: AsRef<Path>>(path: P) -> Result<Vec<String>, Error> {
+    let mut result = Vec::new();
+    for component in path.as_ref().components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => continue,
+            Component::ParentDir => {
+                return Err(anyhow!("Object path must not contain \"..\""));
+            }
+            Component::Normal(name) => result.push(
+                name.to_str()
+                    .ok_or_else(|| anyhow!("Error converting object path"))?
+                    .to_owned(),
+            ),
+        }
+    }
+    ensure!(!result.is_empty(), "Object path is empty");
+    Ok(result)
+}
+
 pub fn generate_tree<P: AsRef<Path>>(path: P, follow_symlinks: bool) -> io::Result<Vec<PathBuf>> {
-    let path = path.as_ref();
+    generate_tree_with_depth(path, follow_symlinks, None)
+}
+
+pub fn generate_tree_iter<P: AsRef<Path>>(
+    path: P,
+    follow_symlinks: bool,
+) -> impl Iterator<Item = io::Result<PathBuf>> {
+    TreeIter {
+        stack: vec![(path.as_ref().to_path_buf(), Rc::new(Vec::new()))],
+        follow_symlinks,
+    }
+}
+
+#[cfg(unix)]
+fn directory_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+// Without device and inode numbers, symlink cycles are only bounded by max_depth.
+#[cfg(not(unix))]
+fn directory_id(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+// A directory is only skipped when it is one of its own ancestors, so several links to the same directory are all kept.
+fn is_cycle(ancestors: &[(u64, u64)], id: Option<(u64, u64)>) -> bool {
+    id.map_or(false, |id| ancestors.contains(&id))
+}
+
+struct TreeIter {
+    stack: Vec<(PathBuf, Rc<Vec<(u64, u64)>>)>,
+    follow_symlinks: bool,
+}
+
+impl TreeIter {
+    fn push_children(&mut self, path: &Path, ancestors: &[(u64, u64)]) -> io::Result<()> {
+        let metadata = match self.follow_symlinks {
+            true => fs::metadata(path)?,
+            false => fs::symlink_metadata(path)?,
+        };
+        let id = directory_id(&metadata);
+        if metadata.is_dir() && !is_cycle(ancestors, id) {
+            let mut ancestors = ancestors.to_vec();
+            ancestors.extend(id);
+            let ancestors = Rc::new(ancestors);
+            let mut children = Vec::new();
+            for entry in fs::read_dir(path)? {
+                children.push((entry?.path(), ancestors.clone()));
+            }
+            self.stack.extend(children.into_iter().rev());
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for TreeIter {
+    type Item = io::Result<PathBuf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, ancestors) = self.stack.pop()?;
+        Some(self.push_children(&path, &ancestors).map(|_| path))
+    }
+}
+
+/// `max_depth` of `Some(0)` yields only `path` itself, `None` means unlimited depth.
+pub fn generate_tree_with_depth<P: AsRef<Path>>(
+    path: P,
+    follow_symlinks: bool,
+    max_depth: Option<usize>,
+) -> io::Result<Vec<PathBuf>> {
     let mut result = Vec::new();
+    collect_tree(
+        path.as_ref(),
+        follow_symlinks,
+        max_depth,
+        &mut Vec::new(),
+        &mut result,
+    )?;
+    Ok(result)
+}
+
+fn collect_tree(
+    path: &Path,
+    follow_symlinks: bool,
+    max_depth: Option<usize>,
+    ancestors: &mut Vec<(u64, u64)>,
+    result: &mut Vec<PathBuf>,
+) -> io::Result<()> {
     result.push(path.to_path_buf());
+    if max_depth == Some(0) {
+        return Ok(());
+    }
     let metadata = match follow_symlinks {
         true => fs::metadata(path)?,
         false => fs::symlink_metadata(path)?,
     };
-    if metadata.is_dir() {
+    let id = directory_id(&metadata);
+    if metadata.is_dir() && !is_cycle(ancestors, id) {
+        ancestors.extend(id);
         for entry in fs::read_dir(path)? {
             let entry = entry?;
-            result.extend(generate_tree(entry.path(), follow_symlinks)?);
+            collect_tree(
+                &entry.path(),
+                follow_symlinks,
+                max_depth.map(|depth| depth - 1),
+                ancestors,
+                result,
+            )?;
+        }
+        if id.is_some() {
+            ancestors.pop();
         }
     }
-    Ok(result)
+    Ok(())
+}
+
+/// Random part of temporary file names, so that they cannot be predicted and prepared in advance
+pub fn temp_nonce() -> String {
+    sodium::to_hex(&sodium::randombytes(8))
+}
+
+pub fn temp_path<P: AsRef<Path>>(path: P, nonce: &str) -> Result<PathBuf, Error> {
+    let path = path.as_ref();
+    let mut filename = OsString::from(".");
+    filename.push(
+        path.file_name()
+            .ok_or_else(|| anyhow!("Error getting filename component"))?,
+    );
+    filename.push(format!(".{}.tmp", nonce));
+    Ok(path.with_file_name(filename))
+}
+
+/// Creates a file that must not exist yet, refusing to follow or reuse anything already at `path`
+pub fn create_new_file<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+pub fn atomic_write<P, F>(path: P, f: F) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut File) -> Result<(), Error>,
+{
+    let temp_path = temp_path(path.as_ref(), &temp_nonce())?;
+    let mut file = create_new_file(&temp_path).context("Error creating temporary file")?;
+    if let Err(err) = f(&mut file).and_then(|_| file.sync_all().map_err(Error::from)) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err);
+    }
+    fs::rename(&temp_path, path.as_ref()).context("Error renaming temporary file")?;
+    Ok(())
 }
 
 pub struct EmptyWriter {}
@@ -69,6 +255,48 @@ impl Write for EmptyWriter {
     }
 }
 
+#[derive(Default)]
+pub struct CountingWriter {
+    pub bytes_written: u64,
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes_written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct MultiWriter {
+    writers: Vec<Box<dyn Write>>,
+}
+
+impl MultiWriter {
+    pub fn new(writers: Vec<Box<dyn Write>>) -> Self {
+        Self { writers }
+    }
+}
+
+impl Write for MultiWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for writer in self.writers.iter_mut() {
+            writer.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for writer in self.writers.iter_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
 pub struct HashingWriter<W: Write> {
     inner: Option<W>,
     hasher: hashing::Hasher,
@@ -134,9 +362,30 @@ pub mod codecs {
     }
 }
 
+/// Creates a fresh directory under the system temp dir that no other test or test run shares
+#[cfg(test)]
+pub fn test_dir(name: &str) -> PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let dir = env::temp_dir().join(format!(
+        "secrets_{}_{}_{}",
+        name,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::utils::{generate_tree, parse_size};
+    use crate::utils::{
+        atomic_write, create_new_file, generate_tree, generate_tree_iter, generate_tree_with_depth,
+        get_path_components, parse_size, temp_nonce, temp_path, test_dir,
+    };
+    use anyhow::anyhow;
+    use std::fs;
+    use std::io::Write;
 
     #[test]
     fn size_test() {
@@ -144,21 +393,126 @@ mod tests {
         assert_eq!(parse_size("4G").unwrap(), 4 * 1024 * 1024 * 1024);
         assert_eq!(parse_size("512M").unwrap(), 512 * 1024 * 1024);
         assert_eq!(parse_size("128K").unwrap(), 128 * 1024);
+        assert_eq!(parse_size("1.5G").unwrap(), 1_610_612_736);
+        assert_eq!(parse_size("0.5M").unwrap(), 524_288);
+        assert_eq!(parse_size("500.0").unwrap(), 500);
+        assert!(parse_size("1.2.3M").is_err());
+    }
+
+    #[test]
+    fn path_components_test() {
+        assert_eq!(get_path_components("a/b").unwrap(), ["a", "b"]);
+        assert_eq!(get_path_components("./a/b").unwrap(), ["a", "b"]);
+        assert_eq!(get_path_components("/a/./b/").unwrap(), ["a", "b"]);
+        assert!(get_path_components("/").is_err());
+        assert!(get_path_components(".").is_err());
+        assert!(get_path_components("../x").is_err());
+        assert!(get_path_components("a/../x").is_err());
     }
 
     #[test]
     fn tree_test() {
-        for p in generate_tree("/home/zhenyan/git/spdlog", true)
-            .unwrap()
-            .iter()
-        {
-            println!("{:?}", p);
-        }
+        let root = test_dir("tree_test");
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/file"), b"").unwrap();
+        fs::write(root.join("a/b/file"), b"").unwrap();
+        let mut tree = generate_tree(&root, true).unwrap();
+        tree.sort();
+        let expected = vec![
+            root.clone(),
+            root.join("a"),
+            root.join("a/b"),
+            root.join("a/b/file"),
+            root.join("a/file"),
+        ];
+        assert_eq!(tree, expected);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn tree_depth_test() {
+        let root = test_dir("tree_depth_test");
+        fs::create_dir_all(root.join("a/b/c")).unwrap();
+        let count = |depth| generate_tree_with_depth(&root, true, depth).unwrap().len();
+        assert_eq!(count(Some(0)), 1);
+        assert_eq!(count(Some(1)), 2);
+        assert_eq!(count(Some(2)), 3);
+        assert_eq!(count(None), 4);
+        let lazy: Vec<_> = generate_tree_iter(&root, true)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(lazy, generate_tree(&root, true).unwrap());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn atomic_write_test() {
+        let dir = test_dir("atomic_write_test");
+        let path = dir.join("file");
+        assert!(atomic_write(&path, |file| {
+            file.write_all(b"partial")?;
+            Err(anyhow!("Aborted"))
+        })
+        .is_err());
+        assert!(!path.exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        atomic_write(&path, |file| Ok(file.write_all(b"content")?)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"content");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn temp_path_test() {
+        let dir = test_dir("temp_path_test");
+        let path = dir.join("file");
+        let nonce = temp_nonce();
+        assert_ne!(nonce, temp_nonce());
+        let temp = temp_path(&path, &nonce).unwrap();
+        assert_eq!(temp.parent(), Some(dir.as_path()));
+        create_new_file(&temp).unwrap();
+        assert!(create_new_file(&temp).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_cycle_test() {
+        let dir = test_dir("symlink_cycle_test");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("sub").join("loop")).unwrap();
+        assert_eq!(generate_tree(&dir, true).unwrap().len(), 3);
+        assert_eq!(generate_tree_iter(&dir, true).count(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shared_symlink_test() {
+        let dir = test_dir("shared_symlink_test");
+        fs::create_dir_all(dir.join("shared")).unwrap();
+        fs::write(dir.join("shared").join("file"), b"").unwrap();
+        std::os::unix::fs::symlink(dir.join("shared"), dir.join("first")).unwrap();
+        std::os::unix::fs::symlink(dir.join("shared"), dir.join("second")).unwrap();
+        let tree = generate_tree(&dir, true).unwrap();
+        assert_eq!(tree.len(), 7);
+        assert!(tree.contains(&dir.join("first").join("file")));
+        assert!(tree.contains(&dir.join("second").join("file")));
+        assert_eq!(generate_tree_iter(&dir, true).count(), 7);
+        fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
     fn symlink_test() {
-        let l = generate_tree("/tmp/td/", true).unwrap();
-        l.iter().for_each(|item| println!("{:?}", item));
+        let dir = test_dir("symlink_test");
+        fs::write(dir.join("file"), b"").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("file"), dir.join("link")).unwrap();
+        let tree = generate_tree(&dir, true).unwrap();
+        assert!(tree.contains(&dir.join("file")));
+        #[cfg(unix)]
+        assert!(tree.contains(&dir.join("link")));
+        assert_eq!(tree.len(), if cfg!(unix) { 3 } else { 2 });
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
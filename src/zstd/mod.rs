@@ -2,7 +2,7 @@ use std::os::raw::c_void;
 
 use crate::buffer;
 use crate::buffer::Buffer;
-use failure::{format_err, Error};
+use anyhow::{anyhow, Error};
 
 #[allow(
     dead_code,
@@ -16,7 +16,7 @@ fn try_to(code: usize) -> Result<usize, Error> {
     unsafe {
         match _zstd::ZSTD_isError(code) {
             0 => Ok(code),
-            _ => Err(format_err!(
+            _ => Err(anyhow!(
                 "ZSTD error: {}",
                 std::ffi::CStr::from_ptr(_zstd::ZSTD_getErrorName(code))
                     .to_str()
@@ -26,10 +26,84 @@ fn try_to(code: usize) -> Result<usize, Error> {
     }
 }
 
+pub fn compress_all(data: &[u8], level: i32) -> Result<Vec<u8>, Error> {
+    unsafe {
+        let mut output = vec![0u8; _zstd::ZSTD_compressBound(data.len())];
+        let size = try_to(_zstd::ZSTD_compress(
+            output.as_mut_ptr() as *mut c_void,
+            output.len(),
+            data.as_ptr() as *const c_void,
+            data.len(),
+            level,
+        ))?;
+        output.truncate(size);
+        Ok(output)
+    }
+}
+
+pub fn decompress_all(data: &[u8], max_size: usize) -> Result<Vec<u8>, Error> {
+    unsafe {
+        let content_size =
+            _zstd::ZSTD_getFrameContentSize(data.as_ptr() as *const c_void, data.len());
+        if content_size == _zstd::ZSTD_CONTENTSIZE_ERROR as u64 {
+            return Err(anyhow!("Invalid zstd frame"));
+        }
+        if content_size == _zstd::ZSTD_CONTENTSIZE_UNKNOWN as u64 {
+            return Err(anyhow!("Unknown zstd frame content size"));
+        }
+        if content_size > max_size as u64 {
+            return Err(anyhow!(
+                "zstd frame content size {} exceeds the limit of {} bytes",
+                content_size,
+                max_size
+            ));
+        }
+        let mut output = vec![0u8; content_size as usize];
+        let size = try_to(_zstd::ZSTD_decompress(
+            output.as_mut_ptr() as *mut c_void,
+            output.len(),
+            data.as_ptr() as *const c_void,
+            data.len(),
+        ))?;
+        output.truncate(size);
+        Ok(output)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CompressionStats {
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub ratio: f64,
+}
+
+impl CompressionStats {
+    pub fn new(input_bytes: u64, output_bytes: u64) -> Self {
+        Self {
+            input_bytes,
+            output_bytes,
+            ratio: if output_bytes == 0 {
+                0.0
+            } else {
+                input_bytes as f64 / output_bytes as f64
+            },
+        }
+    }
+
+    pub fn add(&self, other: &CompressionStats) -> Self {
+        Self::new(
+            self.input_bytes + other.input_bytes,
+            self.output_bytes + other.output_bytes,
+        )
+    }
+}
+
 pub struct Compressor {
     ctx: *mut _zstd::ZSTD_CCtx,
     output_buf: Vec<u8>,
     buf: buffer::Buffer,
+    input_bytes: u64,
+    output_bytes: u64,
 }
 
 pub struct Decompressor {
@@ -46,6 +120,8 @@ impl Compressor {
                 ctx: _zstd::ZSTD_createCCtx(),
                 output_buf: vec![0u8; _zstd::ZSTD_CStreamOutSize()],
                 buf: Buffer::with_capacity(_zstd::ZSTD_CStreamOutSize() * 2),
+                input_bytes: 0,
+                output_bytes: 0,
             };
             _zstd::ZSTD_CCtx_setParameter(
                 result.ctx,
@@ -61,6 +137,22 @@ impl Compressor {
         }
     }
 
+    pub fn new_threaded(compression_level: i32, threads: usize) -> Result<Self, Error> {
+        let result = Self::new(compression_level);
+        unsafe {
+            try_to(_zstd::ZSTD_CCtx_setParameter(
+                result.ctx,
+                _zstd::ZSTD_cParameter_ZSTD_c_nbWorkers,
+                threads as i32,
+            ))?;
+        }
+        Ok(result)
+    }
+
+    pub fn stats(&self) -> CompressionStats {
+        CompressionStats::new(self.input_bytes, self.output_bytes)
+    }
+
     pub fn finish(&mut self) -> Result<&[u8], Error> {
         unsafe {
             let mut output = _zstd::ZSTD_outBuffer {
@@ -74,6 +166,7 @@ impl Compressor {
                     &mut output as *mut _zstd::ZSTD_outBuffer,
                 ))?;
                 self.buf.put(&self.output_buf[0..output.pos]);
+                self.output_bytes += output.pos as u64;
                 if ret == 0 {
                     break;
                 }
@@ -101,8 +194,10 @@ impl Compressor {
                     &mut input as *mut _zstd::ZSTD_inBuffer,
                 ))?;
                 self.buf.put(&self.output_buf[0..output.pos]);
+                self.output_bytes += output.pos as u64;
                 output.pos = 0;
             }
+            self.input_bytes += buf.len() as u64;
             Ok(self.buf.as_slice())
         }
     }
@@ -119,6 +214,7 @@ impl Compressor {
                     &mut output as *mut _zstd::ZSTD_outBuffer,
                 ))?;
                 self.buf.put(&self.output_buf[0..output.pos]);
+                self.output_bytes += output.pos as u64;
                 if ret == 0 {
                     break;
                 }
@@ -149,6 +245,36 @@ impl Decompressor {
         }
     }
 
+    pub fn decompressed_size_estimate(compressed: &[u8]) -> Option<u64> {
+        let size = unsafe {
+            _zstd::ZSTD_getFrameContentSize(compressed.as_ptr() as *const c_void, compressed.len())
+        };
+        if size == _zstd::ZSTD_CONTENTSIZE_UNKNOWN as u64
+            || size == _zstd::ZSTD_CONTENTSIZE_ERROR as u64
+        {
+            None
+        } else {
+            Some(size)
+        }
+    }
+
+    pub fn reset(&mut self) -> Result<(), Error> {
+        unsafe {
+            try_to(_zstd::ZSTD_DCtx_reset(
+                self.ctx,
+                _zstd::ZSTD_ResetDirective_ZSTD_reset_session_only,
+            ))?;
+        }
+        self.buf.clear();
+        self.frame_ended = false;
+        Ok(())
+    }
+
+    /// Whether the data passed to `decompress` so far ended exactly at the end of a frame
+    pub fn frame_ended(&self) -> bool {
+        self.frame_ended
+    }
+
     pub fn decompress(&mut self, buf: &[u8]) -> Result<&[u8], Error> {
         unsafe {
             let mut input = _zstd::ZSTD_inBuffer {
@@ -171,10 +297,55 @@ impl Decompressor {
                 self.buf.put(&self.output_buf[0..output.pos]);
                 output.pos = 0;
             }
-            if let Some(0) = ret {
-                self.frame_ended = true;
+            if let Some(ret) = ret {
+                self.frame_ended = ret == 0;
             }
             Ok(self.buf.as_slice())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::zstd::{compress_all, decompress_all, Compressor, Decompressor};
+
+    #[test]
+    fn one_shot_test() {
+        let data = b"Hello, world! Hello, world! Hello, world!".repeat(16);
+        let compressed = compress_all(&data, 3).unwrap();
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress_all(&compressed, data.len()).unwrap(), data);
+        assert!(decompress_all(&compressed, data.len() - 1).is_err());
+        assert!(decompress_all(b"not zstd", data.len()).is_err());
+        assert_eq!(
+            Decompressor::decompressed_size_estimate(&compressed),
+            Some(data.len() as u64)
+        );
+        assert_eq!(Decompressor::decompressed_size_estimate(b"not zstd"), None);
+    }
+
+    #[test]
+    fn threaded_test() {
+        let data = b"Hello, world! Hello, world! Hello, world!".repeat(16);
+        let mut compressor = Compressor::new_threaded(3, 0).unwrap();
+        let mut compressed = compressor.compress(&data).unwrap().to_vec();
+        compressed.extend_from_slice(compressor.finish().unwrap());
+        let mut decompressor = Decompressor::new();
+        assert_eq!(decompressor.decompress(&compressed).unwrap(), &data[..]);
+    }
+
+    #[test]
+    fn frame_ended_test() {
+        let data = b"Hello, world! Hello, world! Hello, world!".repeat(16);
+        let compressed = compress_all(&data, 3).unwrap();
+        let mut decompressor = Decompressor::new();
+        decompressor
+            .decompress(&compressed[..compressed.len() / 2])
+            .unwrap();
+        assert!(!decompressor.frame_ended());
+        decompressor.reset().unwrap();
+        assert!(!decompressor.frame_ended());
+        assert_eq!(decompressor.decompress(&compressed).unwrap(), &data[..]);
+        assert!(decompressor.frame_ended());
+    }
+}